@@ -0,0 +1,47 @@
+//! Time the generation of a tall column of chunks, with and without the column cache.
+//! Run with `cargo bench --bench worldgen`.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+use voxel_rs::block::{create_headless_block_registry, load_block_definitions, ChunkPos};
+use voxel_rs::config::Config;
+use voxel_rs::server::worldgen::create_generator;
+
+/// Vertical chunk positions of the column
+const MIN_Y: i64 = -16;
+const MAX_Y: i64 = 8;
+/// Number of columns generated by every run
+const COLUMNS: i64 = 4;
+
+fn generate_columns(config: &Config) -> Duration {
+    let definitions = load_block_definitions(Path::new("assets/blocks.toml"));
+    let block_registry = create_headless_block_registry(&definitions);
+    let mut generator = create_generator("default", 42, config, &block_registry);
+    let start = Instant::now();
+    for x in 0..COLUMNS {
+        for y in MIN_Y..MAX_Y {
+            generator.generate(ChunkPos([x, y, 0]));
+        }
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let mut config: Config = toml::from_str("").unwrap();
+    let chunks = (COLUMNS * (MAX_Y - MIN_Y)) as u32;
+
+    let mut times = Vec::new();
+    for &column_cache in &[0, config.worldgen_column_cache] {
+        config.worldgen_column_cache = column_cache;
+        let time = generate_columns(&config);
+        println!(
+            "column cache of {:>4}: {:?} for {} chunks, {:?} per chunk",
+            column_cache,
+            time,
+            chunks,
+            time / chunks
+        );
+        times.push(time.as_secs() as f64 + time.subsec_nanos() as f64 / 1_000_000_000.0);
+    }
+    println!("speedup: {:.2}x", times[0] / times[1]);
+}
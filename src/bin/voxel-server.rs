@@ -0,0 +1,394 @@
+//! Dedicated server. It only starts the server threads, without any window or GL context.
+//! `voxel-server [world]` serves the world in the given directory, or in the configured one, until
+//! `stop` is typed. The `vox` command copies MagicaVoxel files into the world and back.
+//! `voxel-server preview` draws the terrain of the configured generator instead, see
+//! `PREVIEW_USAGE`.
+//! `voxel-server vox` runs the `vox` command on a world that isn't running, see `VOX_USAGE`.
+
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use voxel_rs::block::{
+    create_headless_block_registry, load_block_definitions, BlockDefinition, BlockId, BlockPos,
+    BlockRegion, BlockRegistry, ChunkPos, PalettedChunk,
+};
+use voxel_rs::config::{load_config, Config};
+use voxel_rs::network::tcp::TcpServer;
+use voxel_rs::server::worldgen::preview::{block_colors, render_preview, PreviewArea, PreviewMode};
+use voxel_rs::server::worldgen::{create_generator, WorldGenerator};
+use voxel_rs::server::ServerHandle;
+use voxel_rs::vox::{
+    load_color_table, load_vox, region_to_scene, save_vox, scene_to_region, ColorTable,
+};
+use voxel_rs::world::World;
+
+const PREVIEW_USAGE: &str = "\
+Usage: voxel-server preview [options]
+Draw the terrain of a new world with the generator of cfg/server.toml, seen from above.
+Options:
+    --mode <blocks|height|caves>  highest blocks, shaded heights, or a slice at --y (blocks)
+    --y <height>                  height of the slice of the caves mode (0)
+    --area <x,z,width,depth>      columns of chunks to draw (-8,-8,16,16)
+    --range <min_y,max_y>         chunks searched for the highest blocks (-4,8)
+    --seed <seed>                 use this seed instead of the configured one
+    --generator <name>            use this generator instead of the configured one
+    --output <file>               PNG file to write (preview.png)";
+
+const VOX_USAGE: &str = "\
+Usage: voxel-server vox import <file> <x,y,z> [--world <dir>]
+       voxel-server vox export <file> <x,y,z> <width,height,depth> [--world <dir>]
+Copy a MagicaVoxel file into a world with its lowest corner at x,y,z, or a box of blocks of a
+world into a MagicaVoxel file. Colours and blocks are matched with assets/vox_colors.toml.
+The world is the configured one by default. It must not be running: use the vox command of the
+server instead. Chunks that were never generated are generated first.";
+
+const COMMANDS: &str = "\
+Commands:
+    stop                              save the world and stop the server
+    vox import <file> <x,y,z>         copy a MagicaVoxel file into the world
+    vox export <file> <x,y,z> <size>  copy a box of width,height,depth blocks into a MagicaVoxel
+                                      file";
+
+fn main() {
+    // Load config
+    std::fs::create_dir_all(Path::new("cfg")).unwrap();
+    let config = load_config(Path::new("cfg/server.toml"));
+
+    // Blocks
+    let block_definitions = load_block_definitions(Path::new("assets/blocks.toml"));
+    let block_registry = Arc::new(create_headless_block_registry(&block_definitions));
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|arg| &arg[..]) == Some("preview") {
+        preview(&args[1..], config, &block_definitions, &block_registry);
+        return;
+    }
+    if args.first().map(|arg| &arg[..]) == Some("vox") {
+        vox(&args[1..], config, &block_registry);
+        return;
+    }
+    let colors = load_color_table(Path::new("assets/vox_colors.toml"), &block_registry);
+
+    let config = Arc::new(config);
+    let server = TcpServer::bind(&config.server_address[..])
+        .unwrap_or_else(|e| panic!("Failed to listen on {}: {}", config.server_address, e));
+    println!("Listening on {}", config.server_address);
+
+    let world_path = args
+        .first()
+        .cloned()
+        .unwrap_or_else(|| config.world_path.clone());
+    let mut server =
+        voxel_rs::server::start(server, Path::new(&world_path), config, block_registry);
+
+    // Commands
+    let stdin = std::io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                println!("Failed to read the commands: {}", e);
+                break;
+            }
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match &words[..] {
+            &[] => (),
+            &["stop"] => {
+                server.stop();
+                return;
+            }
+            _ if words[0] == "vox" => {
+                match parse_vox_command(&words[1..])
+                    .and_then(|command| run_vox_command(command, &colors, &mut server))
+                {
+                    Ok(message) => println!("{}", message),
+                    Err(e) => println!("vox: {}", e),
+                }
+            }
+            _ => println!("Unknown command {}\n{}", line.trim(), COMMANDS),
+        }
+    }
+    // Without commands, the server runs until it is killed
+    server.join();
+}
+
+fn preview(
+    args: &[String],
+    config: Config,
+    block_definitions: &[BlockDefinition],
+    block_registry: &BlockRegistry,
+) {
+    let mut mode = "blocks".to_owned();
+    let mut y = 0;
+    let mut area = PreviewArea {
+        x: -8,
+        z: -8,
+        width: 16,
+        depth: 16,
+        min_y: -4,
+        max_y: 8,
+    };
+    let mut seed = config.world_seed;
+    let mut generator_name = config.world_generator.clone();
+    let mut output = "preview.png".to_owned();
+
+    let mut args = args.iter();
+    while let Some(option) = args.next() {
+        let value = match args.next() {
+            Some(value) => value,
+            None => usage_error(&format!("missing value for {}", option)),
+        };
+        match &option[..] {
+            "--mode" => mode = value.clone(),
+            "--y" => y = parse(option, value),
+            "--area" => match &parse_list(option, value)[..] {
+                &[x, z, width, depth] if width > 0 && depth > 0 => {
+                    area = PreviewArea {
+                        x,
+                        z,
+                        width,
+                        depth,
+                        ..area
+                    }
+                }
+                _ => usage_error("--area expects x,z,width,depth"),
+            },
+            "--range" => match &parse_list(option, value)[..] {
+                &[min_y, max_y] if min_y < max_y => {
+                    area.min_y = min_y;
+                    area.max_y = max_y;
+                }
+                _ => usage_error("--range expects min_y,max_y"),
+            },
+            "--seed" => seed = Some(parse(option, value)),
+            "--generator" => generator_name = value.clone(),
+            "--output" => output = value.clone(),
+            _ => usage_error(&format!("unknown option {}", option)),
+        }
+    }
+    let mode = match &mode[..] {
+        "blocks" => PreviewMode::Blocks,
+        "height" => PreviewMode::Height,
+        "caves" => PreviewMode::Caves(y),
+        _ => usage_error(&format!("unknown mode {}", mode)),
+    };
+
+    // TOML integers are signed
+    let seed = seed.unwrap_or_else(|| rand::random::<u64>() >> 1);
+    println!(
+        "Drawing {} by {} chunks of the {} generator with seed {}",
+        area.width, area.depth, generator_name, seed
+    );
+    let mut generator = create_generator(&generator_name, seed, &config, block_registry);
+    let image = render_preview(
+        &mut *generator,
+        &block_colors(block_definitions),
+        area,
+        mode,
+    );
+    image
+        .save(&output)
+        .unwrap_or_else(|e| panic!("Failed to write {}: {}", output, e));
+    println!("Wrote {}", output);
+}
+
+fn parse<T: std::str::FromStr>(option: &str, value: &str) -> T {
+    value
+        .parse()
+        .unwrap_or_else(|_| usage_error(&format!("invalid value {} for {}", value, option)))
+}
+
+fn parse_list(option: &str, value: &str) -> Vec<i64> {
+    value.split(',').map(|v| parse(option, v.trim())).collect()
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n{}", message, PREVIEW_USAGE);
+    std::process::exit(1)
+}
+
+/// Import or export a MagicaVoxel file without starting the server
+fn vox(args: &[String], config: Config, block_registry: &BlockRegistry) {
+    let mut world_path = config.world_path.clone();
+    let mut words = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--world" {
+            match args.next() {
+                Some(value) => world_path = value.clone(),
+                None => vox_usage_error("missing value for --world"),
+            }
+        } else {
+            words.push(&arg[..]);
+        }
+    }
+    let command = parse_vox_command(&words).unwrap_or_else(|e| vox_usage_error(&e));
+
+    let world_path = Path::new(&world_path);
+    if !world_path.join("world.toml").exists() {
+        vox_usage_error(&format!("there is no world in {}", world_path.display()));
+    }
+    // The world exists, so it doesn't need a spawn point
+    let world = World::open(world_path, &config, block_registry, |_| [0.0; 3])
+        .unwrap_or_else(|e| panic!("Failed to open world {}: {}", world_path.display(), e));
+    let generator = create_generator(
+        &world.info.generator,
+        world.info.seed,
+        &config,
+        block_registry,
+    );
+    let colors = load_color_table(Path::new("assets/vox_colors.toml"), block_registry);
+    let mut world = OfflineWorld { world, generator };
+    match run_vox_command(command, &colors, &mut world) {
+        Ok(message) => println!("{}", message),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn vox_usage_error(message: &str) -> ! {
+    eprintln!("{}\n{}", message, VOX_USAGE);
+    std::process::exit(1)
+}
+
+enum VoxCommand {
+    /// Copy a file into the world, with its lowest corner at the given position
+    Import(PathBuf, [i64; 3]),
+    /// Copy the region of the given size whose lowest corner is at the given position into a file
+    Export(PathBuf, [i64; 3], [usize; 3]),
+}
+
+/// Parse the arguments of the `vox` command
+fn parse_vox_command(args: &[&str]) -> Result<VoxCommand, String> {
+    match args {
+        &["import", file, origin] => Ok(VoxCommand::Import(file.into(), parse_triple(origin)?)),
+        &["export", file, origin, size] => {
+            let size = parse_triple(size)?;
+            if size.contains(&0) {
+                return Err(format!("empty region {}", size_to_string(size)));
+            }
+            Ok(VoxCommand::Export(file.into(), parse_triple(origin)?, size))
+        }
+        _ => Err("expected import <file> <x,y,z> or export <file> <x,y,z> <size>".to_owned()),
+    }
+}
+
+/// Parse `x,y,z`
+fn parse_triple<T: std::str::FromStr + Copy>(value: &str) -> Result<[T; 3], String> {
+    let values = value
+        .split(',')
+        .map(|v| v.trim().parse().ok())
+        .collect::<Option<Vec<T>>>();
+    match values.as_ref().map(|values| &values[..]) {
+        Some(&[x, y, z]) => Ok([x, y, z]),
+        _ => Err(format!("expected x,y,z instead of {}", value)),
+    }
+}
+
+fn size_to_string([x, y, z]: [usize; 3]) -> String {
+    format!("{}x{}x{}", x, y, z)
+}
+
+fn run_vox_command(
+    command: VoxCommand,
+    colors: &ColorTable,
+    blocks: &mut impl WorldBlocks,
+) -> Result<String, String> {
+    match command {
+        VoxCommand::Import(file, origin) => {
+            let scene =
+                load_vox(&file).map_err(|e| format!("failed to read {}: {}", file.display(), e))?;
+            let region = scene_to_region(&scene, colors);
+            let size = region.size();
+            blocks.paste_region(BlockPos(origin), region)?;
+            Ok(format!(
+                "Imported {} ({} blocks) at {:?}",
+                file.display(),
+                size_to_string(size),
+                origin
+            ))
+        }
+        VoxCommand::Export(file, origin, size) => {
+            let region = blocks.capture_region(BlockPos(origin), size)?;
+            let scene = region_to_scene(&region, colors)?;
+            save_vox(&file, &scene)
+                .map_err(|e| format!("failed to write {}: {}", file.display(), e))?;
+            Ok(format!(
+                "Exported {} blocks at {:?} to {}",
+                size_to_string(size),
+                origin,
+                file.display()
+            ))
+        }
+    }
+}
+
+/// The blocks of a world, read and written by the `vox` command
+trait WorldBlocks {
+    fn capture_region(&mut self, origin: BlockPos, size: [usize; 3])
+        -> Result<BlockRegion, String>;
+    fn paste_region(&mut self, origin: BlockPos, region: BlockRegion) -> Result<(), String>;
+}
+
+impl WorldBlocks for ServerHandle {
+    fn capture_region(
+        &mut self,
+        origin: BlockPos,
+        size: [usize; 3],
+    ) -> Result<BlockRegion, String> {
+        ServerHandle::capture_region(self, origin, size)
+    }
+
+    fn paste_region(&mut self, origin: BlockPos, region: BlockRegion) -> Result<(), String> {
+        ServerHandle::paste_region(self, origin, region)
+    }
+}
+
+/// A world that isn't running. Its chunks are read and written directly, and the chunks that
+/// were never generated are generated.
+struct OfflineWorld {
+    world: World,
+    generator: Box<dyn WorldGenerator>,
+}
+
+impl OfflineWorld {
+    fn load_chunk(&mut self, pos: ChunkPos) -> Result<PalettedChunk, String> {
+        match self.world.storage.load_chunk(pos) {
+            Ok(Some(chunk)) => Ok(chunk),
+            Ok(None) => Ok(self.generator.generate(pos)),
+            Err(e) => Err(format!("failed to load chunk {:?}: {}", pos, e)),
+        }
+    }
+}
+
+impl WorldBlocks for OfflineWorld {
+    fn capture_region(
+        &mut self,
+        origin: BlockPos,
+        size: [usize; 3],
+    ) -> Result<BlockRegion, String> {
+        let mut region = BlockRegion::new(size, BlockId::from(0));
+        for pos in region.chunks(origin) {
+            let chunk = self.load_chunk(pos)?;
+            region.copy_from_chunk(origin, pos, &chunk);
+        }
+        Ok(region)
+    }
+
+    fn paste_region(&mut self, origin: BlockPos, region: BlockRegion) -> Result<(), String> {
+        let mut chunks = Vec::new();
+        for pos in region.chunks(origin) {
+            let mut chunk = self.load_chunk(pos)?;
+            if region.paste_into_chunk(origin, pos, &mut chunk, false) {
+                chunks.push((pos, chunk));
+            }
+        }
+        self.world
+            .storage
+            .save_chunks(chunks.iter().map(|(pos, chunk)| (*pos, chunk)))
+            .map_err(|e| format!("failed to save the chunks: {}", e))
+    }
+}
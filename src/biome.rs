@@ -0,0 +1,176 @@
+//! Biomes, which decide the shape and the surface of the terrain in every column of blocks.
+//! The biome of a column is picked from its temperature and humidity, which vary slowly over
+//! the world.
+
+use crate::util::mix_seed;
+use crate::CHUNK_SIZE;
+use noise::{NoiseFn, Perlin, Seedable};
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Biome {
+    Plains,
+    Forest,
+    Desert,
+    Mountains,
+    Tundra,
+}
+
+/// Every biome, in declaration order
+pub const BIOMES: [Biome; 5] = [
+    Biome::Plains,
+    Biome::Forest,
+    Biome::Desert,
+    Biome::Mountains,
+    Biome::Tundra,
+];
+
+/// Biome of every column of a chunk, indexed by `[x][z]`
+pub type ChunkBiomes = [[Biome; CHUNK_SIZE]; CHUNK_SIZE];
+
+/// How a biome shapes the terrain
+pub struct BiomeProperties {
+    /// The biome is picked in the columns whose (temperature, humidity) is the closest to this
+    pub climate: [f64; 2],
+    /// Average terrain height
+    pub base_height: f64,
+    /// How far the terrain goes above and below `base_height`
+    pub height_variation: f64,
+    /// How far the 3D noise moves the terrain away from its height, forming cliffs and overhangs
+    pub overhang: f64,
+    /// Name of the top block of the terrain
+    pub surface_block: &'static str,
+    /// Name of the blocks right under the surface
+    pub subsurface_block: &'static str,
+    /// Chance that each tree candidate of a column of chunks actually grows
+    pub tree_density: f64,
+}
+
+const PLAINS: BiomeProperties = BiomeProperties {
+    climate: [0.0, 0.0],
+    base_height: 0.0,
+    height_variation: 40.0,
+    overhang: 4.0,
+    surface_block: "grass",
+    subsurface_block: "dirt",
+    tree_density: 0.1,
+};
+
+const FOREST: BiomeProperties = BiomeProperties {
+    climate: [0.1, 0.45],
+    base_height: 10.0,
+    height_variation: 60.0,
+    overhang: 6.0,
+    surface_block: "grass",
+    subsurface_block: "dirt",
+    tree_density: 0.8,
+};
+
+const DESERT: BiomeProperties = BiomeProperties {
+    climate: [0.45, -0.35],
+    base_height: 0.0,
+    height_variation: 25.0,
+    overhang: 3.0,
+    surface_block: "sand",
+    subsurface_block: "sand",
+    tree_density: 0.0,
+};
+
+const MOUNTAINS: BiomeProperties = BiomeProperties {
+    climate: [-0.15, -0.4],
+    base_height: 50.0,
+    height_variation: 150.0,
+    overhang: 24.0,
+    surface_block: "stone",
+    subsurface_block: "stone",
+    tree_density: 0.05,
+};
+
+const TUNDRA: BiomeProperties = BiomeProperties {
+    climate: [-0.5, 0.1],
+    base_height: 15.0,
+    height_variation: 50.0,
+    overhang: 8.0,
+    surface_block: "snow",
+    subsurface_block: "dirt",
+    tree_density: 0.15,
+};
+
+impl Biome {
+    pub fn properties(self) -> &'static BiomeProperties {
+        match self {
+            Biome::Plains => &PLAINS,
+            Biome::Forest => &FOREST,
+            Biome::Desert => &DESERT,
+            Biome::Mountains => &MOUNTAINS,
+            Biome::Tundra => &TUNDRA,
+        }
+    }
+}
+
+/// Scale of the climate noise. Climate changes over hundreds of blocks.
+const CLIMATE_SCALE: f64 = 1.0 / 600.0;
+/// Biomes whose climate is at most this much further than the climate of the closest biome are
+/// blended with it, so that the terrain doesn't form cliffs at biome borders
+const BLEND_DISTANCE: f64 = 0.15;
+
+/// Computes the climate and the biome of every column of a world
+pub struct BiomeSource {
+    temperature: Perlin,
+    humidity: Perlin,
+}
+
+impl BiomeSource {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            temperature: Perlin::new().set_seed(mix_seed(seed, &[1]) as u32),
+            humidity: Perlin::new().set_seed(mix_seed(seed, &[2]) as u32),
+        }
+    }
+
+    /// Temperature and humidity of a column, roughly between -1 and 1
+    pub fn climate(&self, x: i64, z: i64) -> [f64; 2] {
+        let point = [x as f64 * CLIMATE_SCALE, z as f64 * CLIMATE_SCALE];
+        [self.temperature.get(point), self.humidity.get(point)]
+    }
+
+    /// Distance between the climate of a column and the climate of every biome
+    fn climate_distances(&self, x: i64, z: i64) -> [f64; 5] {
+        let climate = self.climate(x, z);
+        let mut distances = [0.0; 5];
+        for (distance, biome) in distances.iter_mut().zip(BIOMES.iter()) {
+            let biome_climate = biome.properties().climate;
+            let (dt, dh) = (climate[0] - biome_climate[0], climate[1] - biome_climate[1]);
+            *distance = (dt * dt + dh * dh).sqrt();
+        }
+        distances
+    }
+
+    pub fn biome(&self, x: i64, z: i64) -> Biome {
+        let distances = self.climate_distances(x, z);
+        let mut closest = 0;
+        for i in 1..BIOMES.len() {
+            if distances[i] < distances[closest] {
+                closest = i;
+            }
+        }
+        BIOMES[closest]
+    }
+
+    /// Weight of every biome of `BIOMES` in a column. The weights sum to 1, and they change
+    /// continuously from a column to the next.
+    pub fn weights(&self, x: i64, z: i64) -> [f64; 5] {
+        let distances = self.climate_distances(x, z);
+        let min_distance = distances.iter().cloned().fold(std::f64::INFINITY, f64::min);
+        let mut weights = [0.0; 5];
+        for (weight, distance) in weights.iter_mut().zip(distances.iter()) {
+            let w = f64::max(0.0, 1.0 - (distance - min_distance) / BLEND_DISTANCE);
+            *weight = w * w;
+        }
+        let total: f64 = weights.iter().sum();
+        for weight in weights.iter_mut() {
+            *weight /= total;
+        }
+        weights
+    }
+}
@@ -1,9 +1,11 @@
 //! Various `Block`- and `Chunk`-related data structures.
 
+use crate::biome::ChunkBiomes;
 use crate::texture::TextureRegistry;
 use crate::{Vertex, CHUNK_SIZE};
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 
 /// Block representation
 pub trait Block {
@@ -12,14 +14,57 @@ pub trait Block {
     fn render(&self, vertices: &mut Vec<Vertex>, adj: u8, delta: [u64; 3]);
     /// Does this block hide adjacent blocks ?
     fn is_opaque(&self) -> bool;
+    /// Does this block stop rays and players ?
+    fn is_solid(&self) -> bool;
 }
 
 /// A block's id
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
 pub struct BlockId(pub u16);
 
 pub struct BlockRegistry {
     blocks: Vec<BlockRef>,
+    names: HashMap<String, BlockId>,
+}
+
+/// Conversion between the ids of a remote registry, such as the server's or the one a world was
+/// saved with, and the ids of the local registry.
+pub struct BlockIdMap {
+    /// Local id of every remote id
+    to_local: Vec<BlockId>,
+    /// Remote id of every local id that exists remotely
+    to_remote: HashMap<BlockId, BlockId>,
+    /// Remote blocks that don't exist locally. They are replaced by air.
+    pub missing: Vec<String>,
+}
+
+/// Description of a block, as declared in `assets/blocks.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockDefinition {
+    pub name: String,
+    /// Blocks without textures are not rendered
+    pub textures: Option<BlockTextures>,
+    /// Does this block hide adjacent blocks ?
+    #[serde(default = "default_true")]
+    pub opaque: bool,
+    /// Does this block stop rays and players ?
+    #[serde(default = "default_true")]
+    pub solid: bool,
+}
+
+/// Textures of the faces of a block. `all` is used for the faces that don't have their own.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockTextures {
+    pub all: Option<String>,
+    pub side: Option<String>,
+    pub top: Option<String>,
+    pub bottom: Option<String>,
+}
+
+/// Layout of `assets/blocks.toml`
+#[derive(Deserialize)]
+struct BlockDefinitions {
+    block: Vec<BlockDefinition>,
 }
 
 pub type ChunkFragment = [BlockId; CHUNK_SIZE];
@@ -30,10 +75,50 @@ pub type BlockRef = Box<Block + Send + Sync>;
 /// It is stored as 32-bit integers so that common functions are implemented.
 pub type ChunkInfo = [u32; CHUNK_SIZE * CHUNK_SIZE / 32];
 pub type ChunkMap = HashMap<ChunkPos, ChunkState>;
+/// Name and id of every block of a registry
+pub type BlockTable = HashMap<String, BlockId>;
 
 pub enum ChunkState {
     Generating,
-    Generated(Box<ChunkArray>),
+    Generated(ServerChunk),
+}
+
+/// A chunk of the server's `ChunkMap`
+pub struct ServerChunk {
+    pub blocks: PalettedChunk,
+    /// Biomes of the columns, computed when the chunk is generated or loaded
+    pub biomes: Box<ChunkBiomes>,
+    /// Whether the chunk was modified since it was generated or saved, and must be saved
+    pub dirty: bool,
+    /// Changes every time the chunk is modified. A version is never given to two versions of a
+    /// chunk, even if the chunk was unloaded in between, so that players with an older version
+    /// know that they must get the chunk again.
+    pub version: u64,
+}
+
+impl ServerChunk {
+    /// A chunk that was just generated or loaded
+    pub fn new(blocks: PalettedChunk, biomes: Box<ChunkBiomes>, version: u64) -> Self {
+        Self {
+            blocks,
+            biomes,
+            dirty: false,
+            version,
+        }
+    }
+}
+
+/// The blocks of a chunk, stored as indices into a palette of the different blocks in the chunk.
+/// The indices are packed using as few bits as possible, and no index is stored if the chunk
+/// contains a single kind of block.
+/// Blocks that are no longer used keep their entry in the palette until `compact` is called,
+/// which the server does when it saves the chunk.
+#[derive(Clone, Debug)]
+pub struct PalettedChunk {
+    palette: Vec<BlockId>,
+    /// Bits per index. It is always a power of 2 so that no index spans two words.
+    bits: usize,
+    indices: Vec<u64>,
 }
 
 
@@ -41,7 +126,7 @@ pub enum ChunkState {
 #[derive(Clone, Debug)]
 pub struct Chunk {
     /// Blocks in the chunk
-    pub blocks: Box<ChunkArray>,
+    pub blocks: PalettedChunk,
     /// Empty blocks adjacent to the chunk (1 is for non-opaque, 0 is for opaque)
     pub sides: Box<ChunkSidesArray>,
 }
@@ -53,30 +138,135 @@ pub struct ChunkPos(pub [i64; 3]);
 #[derive(Hash, PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct FragmentPos(pub [usize; 2]);
 
+/// Position of a block in the world
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct BlockPos(pub [i64; 3]);
+
+/// A box of blocks, such as a building imported from a file. The blocks are stored in the same
+/// order as in a `ChunkArray`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockRegion {
+    size: [usize; 3],
+    blocks: Vec<BlockId>,
+}
+
 pub struct BlockCube {
     uvs: [[[f32; 2]; 4]; 6],
+    opaque: bool,
+    solid: bool,
+}
+
+/// A block that is never rendered, such as air
+pub struct BlockInvisible {
+    opaque: bool,
+    solid: bool,
 }
 
-pub struct BlockAir {}
 impl BlockRegistry {
     pub fn new() -> BlockRegistry {
-        BlockRegistry { blocks: Vec::new() }
+        BlockRegistry {
+            blocks: Vec::new(),
+            names: HashMap::new(),
+        }
     }
 
-    pub fn add_block(&mut self, block: BlockRef) -> BlockId {
+    pub fn add_block(&mut self, name: &str, block: BlockRef) -> BlockId {
         self.blocks.push(block);
-        BlockId::from((self.blocks.len() - 1) as u16)
+        let id = BlockId::from((self.blocks.len() - 1) as u16);
+        self.names.insert(name.to_owned(), id);
+        id
     }
 
     pub fn get_block(&self, id: BlockId) -> &BlockRef {
         &self.blocks[id.0 as usize]
     }
+
+    /// Whether a block was registered with this id
+    pub fn contains(&self, id: BlockId) -> bool {
+        (id.0 as usize) < self.blocks.len()
+    }
+
+    /// Get the id of a block from its name
+    pub fn get_id(&self, name: &str) -> Option<BlockId> {
+        self.names.get(name).cloned()
+    }
+
+    pub fn block_table(&self) -> BlockTable {
+        self.names.clone()
+    }
+}
+
+impl BlockIdMap {
+    pub fn new(remote: &BlockTable, local: &BlockRegistry) -> Self {
+        Self::from_tables(remote, &local.block_table())
+    }
+
+    /// Conversion between the ids of two block tables
+    pub fn from_tables(remote: &BlockTable, local: &BlockTable) -> Self {
+        let air = BlockId::from(0);
+        let len = remote
+            .values()
+            .map(|id| id.0 as usize + 1)
+            .max()
+            .unwrap_or(0);
+        let mut map = Self {
+            to_local: vec![air; len],
+            to_remote: HashMap::new(),
+            missing: Vec::new(),
+        };
+        for (name, &remote_id) in remote {
+            match local.get(name) {
+                Some(&local_id) => {
+                    map.to_local[remote_id.0 as usize] = local_id;
+                    map.to_remote.insert(local_id, remote_id);
+                }
+                None => map.missing.push(name.clone()),
+            }
+        }
+        map.missing.sort();
+        map
+    }
+
+    /// Do both registries use the same ids ?
+    pub fn is_identity(&self) -> bool {
+        self.missing.is_empty()
+            && self
+                .to_local
+                .iter()
+                .enumerate()
+                .all(|(i, id)| i == id.0 as usize)
+            && self.to_remote.len() == self.to_local.len()
+    }
+
+    /// Unknown remote ids are replaced by air
+    pub fn to_local(&self, id: BlockId) -> BlockId {
+        self.to_local
+            .get(id.0 as usize)
+            .cloned()
+            .unwrap_or(BlockId(0))
+    }
+
+    /// `None` if the block doesn't exist remotely
+    pub fn to_remote(&self, id: BlockId) -> Option<BlockId> {
+        self.to_remote.get(&id).cloned()
+    }
+}
+
+impl BlockTextures {
+    /// Texture of every face, in the order of the faces of `Block::render`
+    pub fn faces(&self) -> [Option<&str>; 6] {
+        let all = self.all.as_ref().map(|s| &s[..]);
+        let side = self.side.as_ref().map(|s| &s[..]).or(all);
+        let top = self.top.as_ref().map(|s| &s[..]).or(all);
+        let bottom = self.bottom.as_ref().map(|s| &s[..]).or(all);
+        [side, side, side, side, top, bottom]
+    }
 }
 
 impl Chunk {
     pub fn new() -> Chunk {
         Chunk {
-            blocks: Box::new([[[BlockId(0); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]),
+            blocks: PalettedChunk::new(BlockId(0)),
             sides: Box::new([[[0b00000000; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]),
         }
     }
@@ -88,7 +278,7 @@ impl Chunk {
                 for k in 0..CHUNK_SIZE {
                     // Don't render hidden blocks
                     if self.sides[i][j][k] != 0xFF {
-                        blocks.get_block(self.blocks[i][j][k]).render(
+                        blocks.get_block(self.blocks.get(i, j, k)).render(
                             &mut vec,
                             self.sides[i][j][k],
                             [i as u64, j as u64, k as u64],
@@ -101,6 +291,194 @@ impl Chunk {
     }
 }
 
+impl PalettedChunk {
+    /// Create a chunk filled with `block`
+    pub fn new(block: BlockId) -> Self {
+        Self {
+            palette: vec![block],
+            bits: 0,
+            indices: Vec::new(),
+        }
+    }
+
+    pub fn from_array(blocks: &ChunkArray) -> Self {
+        let mut palette = Vec::new();
+        let mut palette_indices = HashMap::new();
+        for chunkyz in blocks.iter() {
+            for chunkz in chunkyz.iter() {
+                for &block in chunkz.iter() {
+                    palette_indices.entry(block).or_insert_with(|| {
+                        palette.push(block);
+                        palette.len() - 1
+                    });
+                }
+            }
+        }
+
+        let mut chunk = Self::new(palette[0]);
+        chunk.palette = palette;
+        chunk.resize(Self::bits_for(chunk.palette.len()));
+        if chunk.bits > 0 {
+            for i in 0..CHUNK_SIZE {
+                for j in 0..CHUNK_SIZE {
+                    for k in 0..CHUNK_SIZE {
+                        chunk.set_index(Self::index(i, j, k), palette_indices[&blocks[i][j][k]]);
+                    }
+                }
+            }
+        }
+        chunk
+    }
+
+    pub fn to_array(&self) -> Box<ChunkArray> {
+        let mut blocks = Box::new([[[self.palette[0]; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]);
+        if self.bits > 0 {
+            for i in 0..CHUNK_SIZE {
+                for j in 0..CHUNK_SIZE {
+                    for k in 0..CHUNK_SIZE {
+                        blocks[i][j][k] = self.get(i, j, k);
+                    }
+                }
+            }
+        }
+        blocks
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> BlockId {
+        self.palette[self.get_index(Self::index(x, y, z))]
+    }
+
+    /// Replace a block. Blocks that are no longer used stay in the palette until `compact` is
+    /// called. The palette is searched linearly: it holds few blocks in practice.
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block: BlockId) {
+        let palette_index = match self.palette.iter().position(|&b| b == block) {
+            Some(palette_index) => palette_index,
+            None => {
+                self.palette.push(block);
+                let bits = Self::bits_for(self.palette.len());
+                if bits > self.bits {
+                    self.resize(bits);
+                }
+                self.palette.len() - 1
+            }
+        };
+        if self.bits > 0 {
+            self.set_index(Self::index(x, y, z), palette_index);
+        }
+    }
+
+    pub fn get_fragment(&self, x: usize, y: usize) -> ChunkFragment {
+        let mut fragment = [self.palette[0]; CHUNK_SIZE];
+        if self.bits > 0 {
+            for (z, block) in fragment.iter_mut().enumerate() {
+                *block = self.get(x, y, z);
+            }
+        }
+        fragment
+    }
+
+    pub fn set_fragment(&mut self, x: usize, y: usize, fragment: &ChunkFragment) {
+        for (z, &block) in fragment.iter().enumerate() {
+            self.set(x, y, z, block);
+        }
+    }
+
+    /// Remove the blocks that are no longer used from the palette and repack the indices with as
+    /// few bits as possible. No index is stored anymore if a single kind of block is left.
+    pub fn compact(&mut self) {
+        if self.bits == 0 {
+            return;
+        }
+        let len = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+        let mut used = vec![false; self.palette.len()];
+        for i in 0..len {
+            used[self.get_index(i)] = true;
+        }
+        if used.iter().all(|&u| u) {
+            return;
+        }
+        // New index of every block of the palette that is still used
+        let mut new_indices = vec![0; self.palette.len()];
+        let mut palette = Vec::new();
+        for (i, &block) in self.palette.iter().enumerate() {
+            if used[i] {
+                new_indices[i] = palette.len();
+                palette.push(block);
+            }
+        }
+        let bits = Self::bits_for(palette.len());
+        let mut compact = Self {
+            palette,
+            bits,
+            indices: vec![0; len * bits / 64],
+        };
+        if bits > 0 {
+            for i in 0..len {
+                compact.set_index(i, new_indices[self.get_index(i)]);
+            }
+        }
+        *self = compact;
+    }
+
+    /// The block filling the chunk, if it contains a single kind of block
+    pub fn uniform_block(&self) -> Option<BlockId> {
+        if self.bits == 0 {
+            Some(self.palette[0])
+        } else {
+            None
+        }
+    }
+
+    fn index(x: usize, y: usize, z: usize) -> usize {
+        (x * CHUNK_SIZE + y) * CHUNK_SIZE + z
+    }
+
+    /// Bits needed to store indices into a palette of length `len`
+    fn bits_for(len: usize) -> usize {
+        let mut bits = 0;
+        while (1 << bits) < len {
+            bits = if bits == 0 { 1 } else { 2 * bits };
+        }
+        bits
+    }
+
+    fn get_index(&self, i: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = 64 / self.bits;
+        let word = self.indices[i / per_word];
+        ((word >> ((i % per_word) * self.bits)) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn set_index(&mut self, i: usize, value: usize) {
+        let per_word = 64 / self.bits;
+        let shift = (i % per_word) * self.bits;
+        let mask = ((1u64 << self.bits) - 1) << shift;
+        let word = &mut self.indices[i / per_word];
+        *word = (*word & !mask) | ((value as u64) << shift);
+    }
+
+    /// Repack the indices using `bits` bits per index
+    fn resize(&mut self, bits: usize) {
+        let len = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+        let mut old = std::mem::replace(
+            self,
+            Self {
+                palette: Vec::new(),
+                bits,
+                indices: vec![0; len * bits / 64],
+            },
+        );
+        self.palette = std::mem::replace(&mut old.palette, Vec::new());
+        if old.bits > 0 {
+            for i in 0..len {
+                self.set_index(i, old.get_index(i));
+            }
+        }
+    }
+}
+
 impl ChunkPos {
     pub fn orthogonal_dist(self, other: ChunkPos) -> u64 {
         let mut maxcoord = 0;
@@ -111,6 +489,174 @@ impl ChunkPos {
     }
 }
 
+impl BlockPos {
+    pub fn chunk_pos(self) -> ChunkPos {
+        let mut ret = [0; 3];
+        for i in 0..3 {
+            ret[i] = self.0[i] / CHUNK_SIZE as i64
+                - if (self.0[i] % CHUNK_SIZE as i64) < 0 {
+                    1
+                } else {
+                    0
+                };
+        }
+        ChunkPos(ret)
+    }
+
+    /// Position of the block inside its chunk
+    pub fn pos_in_chunk(self) -> [usize; 3] {
+        let chunk_pos = self.chunk_pos();
+        let mut ret = [0; 3];
+        for i in 0..3 {
+            ret[i] = (self.0[i] - chunk_pos.0[i] * CHUNK_SIZE as i64) as usize;
+        }
+        ret
+    }
+
+    /// Position of the center of the block
+    pub fn center(self) -> [f64; 3] {
+        let mut ret = [0.0; 3];
+        for i in 0..3 {
+            ret[i] = self.0[i] as f64 + 0.5;
+        }
+        ret
+    }
+}
+
+impl BlockRegion {
+    /// A region filled with one block
+    pub fn new(size: [usize; 3], block: BlockId) -> Self {
+        Self {
+            size,
+            blocks: vec![block; size[0] * size[1] * size[2]],
+        }
+    }
+
+    /// A region holding the given blocks, `None` if there are not as many blocks as the size
+    /// requires
+    pub fn from_blocks(size: [usize; 3], blocks: Vec<BlockId>) -> Option<Self> {
+        if blocks.len() == size[0] * size[1] * size[2] {
+            Some(Self { size, blocks })
+        } else {
+            None
+        }
+    }
+
+    pub fn size(&self) -> [usize; 3] {
+        self.size
+    }
+
+    /// Every block of the region, in the same order as in a `ChunkArray`
+    pub fn blocks(&self) -> &[BlockId] {
+        &self.blocks
+    }
+
+    pub fn get(&self, pos: [usize; 3]) -> BlockId {
+        self.blocks[self.index(pos)]
+    }
+
+    pub fn set(&mut self, pos: [usize; 3], block: BlockId) {
+        let index = self.index(pos);
+        self.blocks[index] = block;
+    }
+
+    fn index(&self, [x, y, z]: [usize; 3]) -> usize {
+        assert!(x < self.size[0] && y < self.size[1] && z < self.size[2]);
+        (x * self.size[1] + y) * self.size[2] + z
+    }
+
+    /// Chunks containing some blocks of the region when its lowest corner is at `origin`
+    pub fn chunks(&self, origin: BlockPos) -> Vec<ChunkPos> {
+        if self.blocks.is_empty() {
+            return Vec::new();
+        }
+        let min = origin.chunk_pos().0;
+        let mut max = origin.0;
+        for i in 0..3 {
+            max[i] += self.size[i] as i64 - 1;
+        }
+        let max = BlockPos(max).chunk_pos().0;
+        let mut chunks = Vec::new();
+        for x in min[0]..=max[0] {
+            for y in min[1]..=max[1] {
+                for z in min[2]..=max[2] {
+                    chunks.push(ChunkPos([x, y, z]));
+                }
+            }
+        }
+        chunks
+    }
+
+    /// Call `f` with the position in the chunk and the position in the region of every block of
+    /// the chunk at `pos` that is inside the region
+    fn for_each_in_chunk(
+        &self,
+        origin: BlockPos,
+        pos: ChunkPos,
+        mut f: impl FnMut([usize; 3], [usize; 3]),
+    ) {
+        let mut min = [0; 3];
+        let mut max = [0; 3];
+        for i in 0..3 {
+            let chunk_min = pos.0[i] * CHUNK_SIZE as i64;
+            min[i] = i64::max(origin.0[i], chunk_min);
+            max[i] = i64::min(
+                origin.0[i] + self.size[i] as i64,
+                chunk_min + CHUNK_SIZE as i64,
+            );
+        }
+        for x in min[0]..max[0] {
+            for y in min[1]..max[1] {
+                for z in min[2]..max[2] {
+                    let block_pos = BlockPos([x, y, z]);
+                    let region_pos = [
+                        (x - origin.0[0]) as usize,
+                        (y - origin.0[1]) as usize,
+                        (z - origin.0[2]) as usize,
+                    ];
+                    f(block_pos.pos_in_chunk(), region_pos);
+                }
+            }
+        }
+    }
+
+    /// Copy the blocks of the chunk at `pos` that are inside the region when its lowest corner is
+    /// at `origin`
+    pub fn copy_from_chunk(&mut self, origin: BlockPos, pos: ChunkPos, chunk: &PalettedChunk) {
+        let mut blocks = Vec::new();
+        self.for_each_in_chunk(origin, pos, |[x, y, z], region_pos| {
+            blocks.push((region_pos, chunk.get(x, y, z)));
+        });
+        for (region_pos, block) in blocks {
+            self.set(region_pos, block);
+        }
+    }
+
+    /// Replace the blocks of the chunk at `pos` that are inside the region when its lowest corner
+    /// is at `origin`, except where the region has air if `skip_air` is set. Returns whether the
+    /// chunk changed.
+    pub fn paste_into_chunk(
+        &self,
+        origin: BlockPos,
+        pos: ChunkPos,
+        chunk: &mut PalettedChunk,
+        skip_air: bool,
+    ) -> bool {
+        let mut changed = false;
+        self.for_each_in_chunk(origin, pos, |[x, y, z], region_pos| {
+            let block = self.get(region_pos);
+            if skip_air && block == BlockId(0) {
+                return;
+            }
+            if chunk.get(x, y, z) != block {
+                chunk.set(x, y, z, block);
+                changed = true;
+            }
+        });
+        changed
+    }
+}
+
 impl From<u16> for BlockId {
     fn from(id: u16) -> Self {
         BlockId(id)
@@ -139,12 +685,21 @@ impl Block for BlockCube {
     }
 
     fn is_opaque(&self) -> bool {
-        true
+        self.opaque
+    }
+
+    fn is_solid(&self) -> bool {
+        self.solid
     }
 }
 
-/// Create a solid block with the provided textures
-pub fn create_block_cube(texture_names: [&str; 6], textures: &TextureRegistry) -> BlockCube {
+/// Create a block with the provided textures
+pub fn create_block_cube(
+    texture_names: [&str; 6],
+    textures: &TextureRegistry,
+    opaque: bool,
+    solid: bool,
+) -> BlockCube {
     let mut uvs = [[[-1.; 2]; 4]; 6];
     for i in 0..6 {
         let rect = textures.get_position(&texture_names[i]);
@@ -154,19 +709,111 @@ pub fn create_block_cube(texture_names: [&str; 6], textures: &TextureRegistry) -
             uvs[i][j][1] = y;
         }
     }
-    BlockCube { uvs }
+    BlockCube { uvs, opaque, solid }
+}
+
+/// Create a block that is never rendered
+pub fn create_block_invisible(opaque: bool, solid: bool) -> BlockInvisible {
+    BlockInvisible { opaque, solid }
+}
+
+/// Load the block definitions. The position of a block in the file is its id, and the first
+/// block must be air.
+pub fn load_block_definitions(path: &Path) -> Vec<BlockDefinition> {
+    let buf = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+    let definitions: BlockDefinitions = toml::from_str(&buf)
+        .unwrap_or_else(|e| panic!("Failed to parse {}: {}", path.display(), e));
+    let definitions = definitions.block;
+
+    let is_air =
+        |b: &BlockDefinition| b.name == "air" && !b.opaque && !b.solid && b.textures.is_none();
+    if !definitions.first().map(is_air).unwrap_or(false) {
+        panic!("{}: the first block must be air", path.display());
+    }
+    let mut names = HashSet::new();
+    for definition in &definitions {
+        if !names.insert(&definition.name[..]) {
+            panic!("{}: duplicate block {}", path.display(), definition.name);
+        }
+        if let Some(ref textures) = definition.textures {
+            if textures.faces().iter().any(Option::is_none) {
+                panic!(
+                    "{}: some faces of block {} have no texture",
+                    path.display(),
+                    definition.name
+                );
+            }
+        }
+    }
+    definitions
 }
 
-/// Create an air block
-pub fn create_block_air() -> BlockAir {
-    BlockAir {}
+/// Names of the textures used by some blocks, without duplicates
+pub fn block_texture_names(definitions: &[BlockDefinition]) -> Vec<String> {
+    let mut names: Vec<String> = definitions
+        .iter()
+        .filter_map(|definition| definition.textures.as_ref())
+        .flat_map(|textures| textures.faces().to_vec())
+        .filter_map(|name| name.map(str::to_owned))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
 }
 
-impl Block for BlockAir {
+/// Create the registry containing every block of the game
+pub fn create_block_registry(
+    definitions: &[BlockDefinition],
+    textures: &TextureRegistry,
+) -> BlockRegistry {
+    let mut br = BlockRegistry::new();
+    for definition in definitions {
+        let block: BlockRef = match definition.textures {
+            Some(ref block_textures) => {
+                let mut texture_names = [""; 6];
+                for (name, face) in texture_names.iter_mut().zip(&block_textures.faces()) {
+                    *name = face.unwrap();
+                }
+                Box::new(create_block_cube(
+                    texture_names,
+                    textures,
+                    definition.opaque,
+                    definition.solid,
+                ))
+            }
+            None => Box::new(create_block_invisible(definition.opaque, definition.solid)),
+        };
+        br.add_block(&definition.name, block);
+    }
+    br
+}
+
+/// Create a registry without any rendering data, for servers that don't load textures
+pub fn create_headless_block_registry(definitions: &[BlockDefinition]) -> BlockRegistry {
+    let mut br = BlockRegistry::new();
+    for definition in definitions {
+        br.add_block(
+            &definition.name,
+            Box::new(create_block_invisible(definition.opaque, definition.solid)),
+        );
+    }
+    br
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Block for BlockInvisible {
     fn render(&self, _: &mut Vec<Vertex>, _: u8, _: [u64; 3]) {}
 
     fn is_opaque(&self) -> bool {
-        false
+        self.opaque
+    }
+
+    fn is_solid(&self) -> bool {
+        self.solid
     }
 }
 
@@ -211,3 +858,104 @@ const NORMALS: [[f32; 3]; 6] = [
     [0., 1., 0.],
     [0., -1., 0.],
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chunk with a different block every few blocks, so that the palette has to grow
+    fn striped_array(kinds: u16) -> Box<ChunkArray> {
+        let mut blocks = Box::new([[[BlockId(0); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]);
+        for i in 0..CHUNK_SIZE {
+            for j in 0..CHUNK_SIZE {
+                for k in 0..CHUNK_SIZE {
+                    blocks[i][j][k] = BlockId(((i + 3 * j + 7 * k) % kinds as usize) as u16);
+                }
+            }
+        }
+        blocks
+    }
+
+    #[test]
+    fn paletted_chunk_resize() {
+        let mut chunk = PalettedChunk::new(BlockId(1));
+        assert_eq!(chunk.uniform_block(), Some(BlockId(1)));
+        // Every new block makes the palette grow, going through every number of bits per index
+        let mut expected = Box::new([[[BlockId(1); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]);
+        for id in 2..300 {
+            let (x, y, z) = (id as usize % CHUNK_SIZE, id as usize / CHUNK_SIZE, 5);
+            chunk.set(x, y, z, BlockId(id));
+            expected[x][y][z] = BlockId(id);
+            assert_eq!(chunk.get(x, y, z), BlockId(id));
+            assert_eq!(chunk.get(0, 0, 0), BlockId(1));
+        }
+        assert_eq!(chunk.uniform_block(), None);
+        assert_eq!(chunk.to_array(), expected);
+        // Replacing a block by one of the palette doesn't change the others
+        chunk.set(2, 0, 5, BlockId(1));
+        expected[2][0][5] = BlockId(1);
+        assert_eq!(chunk.to_array(), expected);
+    }
+
+    #[test]
+    fn paletted_chunk_array_round_trip() {
+        for &kinds in &[1, 2, 3, 5, 17, 300] {
+            let blocks = striped_array(kinds);
+            let chunk = PalettedChunk::from_array(&blocks);
+            assert_eq!(chunk.to_array(), blocks);
+            assert_eq!(chunk.uniform_block().is_some(), kinds == 1);
+        }
+    }
+
+    #[test]
+    fn paletted_chunk_fragments() {
+        let blocks = striped_array(5);
+        let chunk = PalettedChunk::from_array(&blocks);
+        let mut copy = PalettedChunk::new(BlockId(0));
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                let fragment = chunk.get_fragment(x, y);
+                assert_eq!(fragment, blocks[x][y]);
+                copy.set_fragment(x, y, &fragment);
+            }
+        }
+        assert_eq!(copy.to_array(), blocks);
+        let uniform = PalettedChunk::new(BlockId(4));
+        assert_eq!(uniform.get_fragment(3, 7), [BlockId(4); CHUNK_SIZE]);
+    }
+
+    #[test]
+    fn paletted_chunk_compact() {
+        // A block placed and broken in an empty chunk
+        let mut chunk = PalettedChunk::new(BlockId(0));
+        chunk.set(1, 2, 3, BlockId(7));
+        chunk.set(1, 2, 3, BlockId(0));
+        assert_eq!(chunk.uniform_block(), None);
+        chunk.compact();
+        assert_eq!(chunk.uniform_block(), Some(BlockId(0)));
+        assert_eq!(chunk.palette.len(), 1);
+        assert!(chunk.indices.is_empty());
+
+        // Removing all the blocks but two of a striped chunk
+        let mut blocks = striped_array(5);
+        let mut chunk = PalettedChunk::from_array(&blocks);
+        assert_eq!(chunk.bits, 4);
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    if blocks[x][y][z].0 >= 2 {
+                        blocks[x][y][z] = BlockId(1);
+                        chunk.set(x, y, z, BlockId(1));
+                    }
+                }
+            }
+        }
+        chunk.compact();
+        assert_eq!(chunk.palette, vec![BlockId(0), BlockId(1)]);
+        assert_eq!(chunk.bits, 1);
+        assert_eq!(chunk.to_array(), blocks);
+        // Compacting again changes nothing
+        chunk.compact();
+        assert_eq!(chunk.to_array(), blocks);
+    }
+}
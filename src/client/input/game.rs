@@ -7,63 +7,160 @@ impl InputImpl {
     /// Process queued chunk messages
     pub fn process_chunk_messages(&mut self) {
         for message in self.pending_messages.drain(..) {
-            match message {
-                ToInput::NewChunkFragment(pos, fpos, frag) => {
-                    if let Some(data) = self.game_state.chunks.get(&pos) {
-                        let mut data = &mut *data.borrow_mut();
-                        let index = fpos.0[0] * 32 + fpos.0[1];
-                        // New fragment
-                        if data.chunk_info[index / 32] & (1 << (index % 32)) == 0 {
-                            data.chunk_info[index / 32] |= 1 << (index % 32);
-                            data.chunk.blocks[fpos.0[0]][fpos.0[1]] = *frag;
-                            data.fragments += 1;
-                            // TODO: check that the chunk is in render_distance but NOT in (render_distance; rander_distance+1]
-                            // Update adjacent chunks too
-                            Self::check_finalize_chunk(
-                                pos,
-                                data,
-                                &self.game_state.chunks,
-                                &self.game_registries.block_registry,
-                            );
-                        }
+            Self::process_chunk_message(
+                message,
+                &self.game_state.chunks,
+                &self.game_registries.block_registry,
+                &self.meshing_tx,
+            );
+        }
+    }
+
+    /// Process a chunk message
+    fn process_chunk_message(
+        message: ToInput,
+        chunks: &HashMap<ChunkPos, RefCell<ChunkData>>,
+        br: &BlockRegistry,
+        meshing_tx: &Sender<ToMeshing>,
+    ) {
+        match message {
+            ToInput::NewChunk(pos) => {
+                if let Some(data) = chunks.get(&pos) {
+                    // The chunk may have been received already, in which case it is sent again
+                    // because it changed: the old blocks must not be kept. The current mesh is
+                    // kept until the chunk is complete again.
+                    let mut data = data.borrow_mut();
+                    data.chunk.blocks = PalettedChunk::new(BlockId(0));
+                    data.chunk_info = [0; CHUNK_SIZE * CHUNK_SIZE / 32];
+                    data.fragments = 0;
+                    data.biomes = None;
+                } else {
+                    return;
+                }
+                // The adjacent chunks will take their sides from this chunk again once it is
+                // complete
+                for face in 0..6 {
+                    if let Some(c) = chunks.get(&Self::adj_pos(pos, face)) {
+                        c.borrow_mut().adj_chunks &= !(1 << face);
                     }
                 }
-                ToInput::NewChunkInfo(pos, info) => {
-                    if let Some(data) = self.game_state.chunks.get(&pos) {
-                        let mut data = &mut *data.borrow_mut();
-                        for (from, to) in info.iter().zip(data.chunk_info.iter_mut()) {
-                            data.fragments -= to.count_ones() as usize;
-                            *to |= *from;
-                            data.fragments += to.count_ones() as usize;
-                        }
-                        // Update adjacent chunks
-                        Self::check_finalize_chunk(
-                            pos,
-                            data,
-                            &self.game_state.chunks,
-                            &self.game_registries.block_registry,
-                        );
+            }
+            ToInput::NewChunkFragment(pos, fpos, frag) => {
+                if let Some(data) = chunks.get(&pos) {
+                    let mut data = &mut *data.borrow_mut();
+                    let index = fpos.0[0] * 32 + fpos.0[1];
+                    // New fragment
+                    if data.chunk_info[index / 32] & (1 << (index % 32)) == 0 {
+                        data.chunk_info[index / 32] |= 1 << (index % 32);
+                        data.chunk.blocks.set_fragment(fpos.0[0], fpos.0[1], &frag);
+                        data.fragments += 1;
+                        // TODO: check that the chunk is in render_distance but NOT in (render_distance; rander_distance+1]
+                        // Update adjacent chunks too
+                        Self::check_finalize_chunk(pos, data, chunks, br, meshing_tx);
+                    }
+                }
+            }
+            ToInput::NewChunkInfo(pos, info) => {
+                if let Some(data) = chunks.get(&pos) {
+                    let mut data = &mut *data.borrow_mut();
+                    let fragments = data.fragments;
+                    for (from, to) in info.iter().zip(data.chunk_info.iter_mut()) {
+                        data.fragments -= to.count_ones() as usize;
+                        *to |= *from;
+                        data.fragments += to.count_ones() as usize;
+                    }
+                    // Update adjacent chunks
+                    if data.fragments != fragments {
+                        Self::check_finalize_chunk(pos, data, chunks, br, meshing_tx);
+                    }
+                }
+            }
+            ToInput::ChunkBiomes(pos, biomes) => {
+                if let Some(data) = chunks.get(&pos) {
+                    data.borrow_mut().biomes = Some(biomes);
+                }
+            }
+            ToInput::BlockUpdate(pos, block) => {
+                let chunk_pos = pos.chunk_pos();
+                let pos_in_chunk = pos.pos_in_chunk();
+                if let Some(data) = chunks.get(&chunk_pos) {
+                    let [x, y, z] = pos_in_chunk;
+                    data.borrow_mut().chunk.blocks.set(x, y, z, block);
+                } else {
+                    return;
+                }
+                // Remesh the chunk, and the adjacent chunks if the block is on a border
+                Self::remesh_chunk(chunk_pos, chunks, br, meshing_tx);
+                for face in 0..6 {
+                    let adj = ADJ_CHUNKS[face];
+                    let on_border = (0..3).all(|i| match adj[i] {
+                        1 => pos_in_chunk[i] == CHUNK_SIZE - 1,
+                        -1 => pos_in_chunk[i] == 0,
+                        _ => true,
+                    });
+                    if on_border {
+                        Self::remesh_chunk(Self::adj_pos(chunk_pos, face), chunks, br, meshing_tx);
                     }
                 }
-                _ => unreachable!(),
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Position of the chunk adjacent to the given chunk through the given face
+    fn adj_pos(mut pos: ChunkPos, face: usize) -> ChunkPos {
+        for i in 0..3 {
+            pos.0[i] += ADJ_CHUNKS[face][i];
+        }
+        pos
+    }
+
+    /// Recompute the sides of a fully received chunk, and mesh it again if it was already meshed
+    fn remesh_chunk(
+        pos: ChunkPos,
+        chunks: &HashMap<ChunkPos, RefCell<ChunkData>>,
+        br: &BlockRegistry,
+        meshing_tx: &Sender<ToMeshing>,
+    ) {
+        let mut data = match chunks.get(&pos) {
+            Some(c) => c.borrow_mut(),
+            None => return,
+        };
+        if data.fragments != CHUNK_SIZE * CHUNK_SIZE {
+            return;
+        }
+        // The internal sides are computed by the meshing thread, and the external sides are copied
+        // from the adjacent chunks again.
+        *data.chunk.sides = [[[0; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+        for face in 0..6 {
+            if let Some(c) = chunks.get(&Self::adj_pos(pos, face)) {
+                let adj_chunk = c.borrow();
+                if adj_chunk.fragments == CHUNK_SIZE * CHUNK_SIZE {
+                    Self::update_side(face, &adj_chunk.chunk, &mut data.chunk.sides, br);
+                }
             }
         }
+        // Unmeshed chunks are meshed by `fetch_close_chunks` once they are ready
+        if let ChunkState::Unmeshed = data.state {
+            return;
+        }
+        meshing_tx
+            .send(ToMeshing::ComputeChunkMesh(pos, data.chunk.clone()))
+            .unwrap();
     }
 
-    /// Check if the given chunk has been fully received, and update the adjacent chunk's sides if so
+    /// Check if the given chunk has been fully received, and update the adjacent chunk's sides if so.
+    /// Chunks that were meshed before are meshed again, because the chunk was sent again.
     fn check_finalize_chunk(
         pos: ChunkPos,
         data: &mut ChunkData,
         chunks: &HashMap<ChunkPos, RefCell<ChunkData>>,
         br: &BlockRegistry,
+        meshing_tx: &Sender<ToMeshing>,
     ) {
         if data.fragments == CHUNK_SIZE * CHUNK_SIZE {
             for face in 0..6 {
-                let adj = ADJ_CHUNKS[face];
-                let mut pos = pos;
-                for i in 0..3 {
-                    pos.0[i] += adj[i];
-                }
+                let pos = Self::adj_pos(pos, face);
                 if let Some(c) = chunks.get(&pos) {
                     let mut adj_chunk = c.borrow_mut();
                     if adj_chunk.adj_chunks & (1 << face) == 0 {
@@ -76,11 +173,24 @@ impl InputImpl {
                             &mut adj_chunk.chunk.sides,
                             br,
                         );
+                        match adj_chunk.state {
+                            ChunkState::Unmeshed => (),
+                            _ => meshing_tx
+                                .send(ToMeshing::ComputeChunkMesh(pos, adj_chunk.chunk.clone()))
+                                .unwrap(),
+                        }
                     }
                 } else {
                     println!("Warning: LOST INFORMATION. Chunk {:?} is not loaded!", pos);
                 }
             }
+            // Unmeshed chunks are meshed by `fetch_close_chunks` once they are ready
+            match data.state {
+                ChunkState::Unmeshed => (),
+                _ => meshing_tx
+                    .send(ToMeshing::ComputeChunkMesh(pos, data.chunk.clone()))
+                    .unwrap(),
+            }
         }
     }
 
@@ -103,15 +213,7 @@ impl InputImpl {
                     self.game_state
                         .chunks
                         .entry(pos.clone())
-                        .or_insert_with(|| {
-                            RefCell::new(ChunkData {
-                                chunk: Chunk::new(),
-                                fragments: 0,
-                                adj_chunks: 0,
-                                chunk_info: [0; CHUNK_SIZE * CHUNK_SIZE / 32],
-                                state: ChunkState::Unmeshed,
-                            })
-                        });
+                        .or_insert_with(|| RefCell::new(ChunkData::new()));
                 }
             }
         }
@@ -165,6 +267,7 @@ impl InputImpl {
     }
 
     /// Update side [face] in the [sides] of some chunk with its adjacent chunk [c].
+    /// The bits of the side are set or cleared, so that a chunk that was sent again replaces the old side.
     fn update_side(face: usize, c: &Chunk, sides: &mut ChunkSidesArray, br: &BlockRegistry) {
         let adj = ADJ_CHUNKS[face];
         for (int_x, ext_x) in Self::get_range(adj[0], true).zip(Self::get_range(adj[0], false)) {
@@ -173,7 +276,9 @@ impl InputImpl {
                 for (int_z, ext_z) in
                     Self::get_range(adj[2], true).zip(Self::get_range(adj[2], false))
                 {
-                    if !br.get_block(c.blocks[ext_x][ext_y][ext_z]).is_opaque() {
+                    if br.get_block(c.blocks.get(ext_x, ext_y, ext_z)).is_opaque() {
+                        sides[int_x][int_y][int_z] &= !(1 << face);
+                    } else {
                         sides[int_x][int_y][int_z] |= 1 << face;
                     }
                 }
@@ -181,6 +286,25 @@ impl InputImpl {
         }
     }
 
+    /// Get the biome of a block, if it was received
+    pub fn get_biome(&self, pos: BlockPos) -> Option<Biome> {
+        let data = self.game_state.chunks.get(&pos.chunk_pos())?.borrow();
+        let [x, _, z] = pos.pos_in_chunk();
+        data.biomes.as_ref().map(|biomes| biomes[x][z])
+    }
+
+    /// Find the block the player is looking at
+    pub fn get_target(&self) -> Option<RaycastHit> {
+        let camera = &self.input_state.camera;
+        raycast(
+            &self.game_state.chunks,
+            &self.game_registries.block_registry,
+            Vector3::from(camera.get_pos().0),
+            camera.get_cam_dir(),
+            REACH_DISTANCE,
+        )
+    }
+
     /// Draw a frame.
     pub fn render(&mut self) {
         let state = &mut self.rendering_state;
@@ -232,3 +356,102 @@ impl InputImpl {
         state.device.cleanup();
     }
 }
+
+impl BlockSource for HashMap<ChunkPos, RefCell<ChunkData>> {
+    /// Get a block, if its chunk has been fully received
+    fn get_block(&self, pos: BlockPos) -> Option<BlockId> {
+        let data = self.get(&pos.chunk_pos())?.borrow();
+        if data.fragments != CHUNK_SIZE * CHUNK_SIZE {
+            return None;
+        }
+        let [x, y, z] = pos.pos_in_chunk();
+        Some(data.chunk.blocks.get(x, y, z))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{create_headless_block_registry, load_block_definitions, FragmentPos};
+
+    /// Index of the face through which the chunk below is adjacent
+    const BOTTOM: usize = 5;
+
+    /// Send a chunk whose fragments are empty, except the first one if a block is given
+    fn receive_chunk(
+        pos: ChunkPos,
+        fragment: Option<BlockId>,
+        chunks: &HashMap<ChunkPos, RefCell<ChunkData>>,
+        br: &BlockRegistry,
+        meshing_tx: &Sender<ToMeshing>,
+    ) {
+        let mut info = [u32::max_value(); CHUNK_SIZE * CHUNK_SIZE / 32];
+        InputImpl::process_chunk_message(ToInput::NewChunk(pos), chunks, br, meshing_tx);
+        if let Some(block) = fragment {
+            info[0] &= !1;
+            let frag = Box::new([block; CHUNK_SIZE]);
+            let message = ToInput::NewChunkFragment(pos, FragmentPos([0, 0]), frag);
+            InputImpl::process_chunk_message(message, chunks, br, meshing_tx);
+        }
+        let message = ToInput::NewChunkInfo(pos, info);
+        InputImpl::process_chunk_message(message, chunks, br, meshing_tx);
+    }
+
+    #[test]
+    fn resent_chunk_replaces_old_blocks() {
+        let br = create_headless_block_registry(&load_block_definitions(Path::new(
+            "assets/blocks.toml",
+        )));
+        let stone = br.get_id("stone").unwrap();
+        let (meshing_tx, meshing_rx) = channel();
+        let center = ChunkPos([0, 0, 0]);
+        let below = InputImpl::adj_pos(center, BOTTOM);
+        let mut chunks = HashMap::new();
+        chunks.insert(center, RefCell::new(ChunkData::new()));
+        for face in 0..6 {
+            chunks.insert(
+                InputImpl::adj_pos(center, face),
+                RefCell::new(ChunkData::new()),
+            );
+        }
+        for face in 0..6 {
+            let pos = InputImpl::adj_pos(center, face);
+            receive_chunk(pos, None, &chunks, &br, &meshing_tx);
+        }
+
+        // The bottom fragment of the column is stone, which hides the top of the chunk below
+        receive_chunk(center, Some(stone), &chunks, &br, &meshing_tx);
+        {
+            let data = chunks[&center].borrow();
+            assert_eq!(data.fragments, CHUNK_SIZE * CHUNK_SIZE);
+            assert_eq!(data.adj_chunks, 0b0011_1111);
+            assert_eq!(data.chunk.blocks.get(0, 0, 0), stone);
+            assert_eq!(chunks[&below].borrow().chunk.sides[0][CHUNK_SIZE - 1][0], 0);
+        }
+        // Nothing was meshed yet, so nothing is meshed again
+        assert!(meshing_rx.try_recv().is_err());
+        chunks[&center].borrow_mut().state = ChunkState::Meshing;
+        chunks[&below].borrow_mut().state = ChunkState::Meshing;
+
+        // The chunk is sent again without the fragment, which is now empty
+        InputImpl::process_chunk_message(ToInput::NewChunk(center), &chunks, &br, &meshing_tx);
+        assert_eq!(chunks[&center].borrow().fragments, 0);
+        assert_eq!(chunks[&below].borrow().adj_chunks & (1 << BOTTOM), 0);
+        receive_chunk(center, None, &chunks, &br, &meshing_tx);
+        {
+            let data = chunks[&center].borrow();
+            assert_eq!(data.fragments, CHUNK_SIZE * CHUNK_SIZE);
+            assert_eq!(data.chunk.blocks.get(0, 0, 0), BlockId(0));
+            let below = chunks[&below].borrow();
+            assert_eq!(below.adj_chunks & (1 << BOTTOM), 1 << BOTTOM);
+            assert_eq!(below.chunk.sides[0][CHUNK_SIZE - 1][0], 1 << (BOTTOM ^ 1));
+        }
+        // Both the chunk and the chunk below it are meshed again
+        let mut meshed: Vec<_> = meshing_rx
+            .try_iter()
+            .map(|ToMeshing::ComputeChunkMesh(pos, _)| pos.0)
+            .collect();
+        meshed.sort();
+        assert_eq!(meshed, vec![below.0, center.0]);
+    }
+}
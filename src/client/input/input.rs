@@ -35,6 +35,11 @@ impl InputImpl {
                             ElementState::Released => false,
                         };
                         //println!("Key {} pressed ? {}", scancode, pressed);
+                        if scancode == SWITCH_MOVEMENT_MODE && pressed && !self.input_state.keyboard_state.is_key_pressed(scancode) {
+                            self.input_state.movement_mode = self.input_state.movement_mode.next();
+                            println!("Movement mode: {:?}", self.input_state.movement_mode);
+                            self.network_tx.send(ToNetwork::SetMovementMode(self.input_state.movement_mode)).unwrap();
+                        }
                         self.input_state.keyboard_state.update_key(scancode, pressed);
                     },
                     WindowEvent::Focused(foc) => {
@@ -43,12 +48,16 @@ impl InputImpl {
                             self.input_state.keyboard_state.clear();
                         }
                     },
-                    WindowEvent::MouseInput { button, state, .. } => {
-                        if button == MouseButton::Left && state == ElementState::Pressed {
-                            println!("Player position: {:?}", self.input_state.camera.get_pos());
-                            let player_chunk = self.input_state.camera.get_pos().chunk_pos();
-                            let c = self.game_state.chunks.get(&player_chunk).unwrap().borrow();
-                            println!("Player chunk: {:?} (fragment_count: {}, adj_chunks: {}, state: {:?})", player_chunk, c.fragments, c.adj_chunks, c.state);
+                    WindowEvent::MouseInput { button, state: ElementState::Pressed, .. } => {
+                        // Left click breaks the targeted block, right click places the selected block
+                        // in front of it and middle click selects it
+                        if let Some(hit) = self.get_target() {
+                            match (button, hit.adjacent_pos()) {
+                                (MouseButton::Left, _) => self.network_tx.send(ToNetwork::BreakBlock(hit.pos)).unwrap(),
+                                (MouseButton::Right, Some(pos)) => self.network_tx.send(ToNetwork::PlaceBlock(pos, self.input_state.selected_block)).unwrap(),
+                                (MouseButton::Middle, _) => self.input_state.selected_block = hit.block,
+                                _ => {},
+                            }
                         }
                     },
                     _ => {},
@@ -88,7 +97,26 @@ impl InputImpl {
                 ToInput::SetPos(pos) => {
                     self.input_state.camera.set_pos(pos.0);
                 }
-                message @ ToInput::NewChunkFragment(..) | message @ ToInput::NewChunkInfo(..) => {
+                ToInput::SetMovementMode(mode) => {
+                    self.input_state.movement_mode = mode;
+                    println!("Movement mode: {:?}", mode);
+                }
+                ToInput::Spawn(record) => {
+                    self.input_state.camera.set_pos(record.pos);
+                    self.input_state
+                        .camera
+                        .set_yaw_pitch(record.yaw, record.pitch);
+                    self.input_state.movement_mode = record.mode;
+                }
+                ToInput::Disconnect(reason) => {
+                    println!("Disconnected from the server: {}", reason);
+                    self.running = false;
+                }
+                message @ ToInput::NewChunk(..)
+                | message @ ToInput::NewChunkFragment(..)
+                | message @ ToInput::NewChunkInfo(..)
+                | message @ ToInput::ChunkBiomes(..)
+                | message @ ToInput::BlockUpdate(..) => {
                     self.pending_messages.push_back(message);
                 }
             }
@@ -100,7 +128,8 @@ impl InputImpl {
         self.debug_info.cnt += 1;
         self.debug_info.cnt %= 200;
         if self.debug_info.cnt == 0 {
-            println!("FPS: {}", frames);
+            let biome = self.get_biome(self.input_state.camera.get_pos().block_pos());
+            println!("FPS: {}, biome: {:?}", frames, biome);
         }
     }
 
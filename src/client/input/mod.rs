@@ -18,15 +18,19 @@ use gfx::traits::FactoryExt;
 use gfx::Factory;
 use glutin::MouseCursor;
 
+use crate::biome::{Biome, ChunkBiomes};
 use crate::block::{
-    create_block_air, create_block_cube, BlockRegistry, Chunk, ChunkInfo, ChunkPos, ChunkSidesArray,
+    block_texture_names, create_block_registry, load_block_definitions, BlockId, BlockPos,
+    BlockRegistry, Chunk, ChunkInfo, ChunkPos, ChunkSidesArray, PalettedChunk,
 };
 use crate::config::{load_config, Config};
 use crate::core::messages::client::{ToInput, ToMeshing, ToNetwork};
 use crate::input::KeyboardState;
-use crate::player::PlayerInput;
+use crate::player::{MovementMode, PlayerInput, REACH_DISTANCE};
+use crate::raycast::{raycast, BlockSource, RaycastHit};
 use crate::render::camera::*;
 use crate::render::frames::FrameCounter;
+use crate::server::ServerHandle;
 use crate::texture::load_textures;
 use crate::texture::TextureRegistry;
 use crate::util::Ticker;
@@ -79,6 +83,11 @@ pub fn start() {
         // Frames
         implementation.update_frame_count();
     }
+
+    // Save the singleplayer world
+    if let Some(server) = implementation.server.take() {
+        server.stop();
+    }
 }
 
 /// Client input thread's state
@@ -90,6 +99,8 @@ struct InputImpl {
     pending_messages: VecDeque<ToInput>,
     meshing_tx: Sender<ToMeshing>,
     network_tx: Sender<ToNetwork>,
+    /// Server running in the same process, in singleplayer
+    server: Option<ServerHandle>,
     input_state: InputState,
     game_state: ClientGameState,
     rendering_state: RenderingState,
@@ -106,6 +117,9 @@ struct InputState {
     pub keyboard_state: KeyboardState,
     pub camera: Camera,
     pub timer: Instant,
+    /// The block placed by the player
+    pub selected_block: BlockId,
+    pub movement_mode: MovementMode,
 }
 
 /// Game-related state
@@ -152,6 +166,22 @@ struct ChunkData {
     pub chunk_info: ChunkInfo,
     /// The chunk's state
     pub state: ChunkState,
+    /// Biome of every column of the chunk, if it was received
+    pub biomes: Option<Box<ChunkBiomes>>,
+}
+
+impl ChunkData {
+    /// A chunk of which nothing has been received yet
+    fn new() -> Self {
+        Self {
+            chunk: Chunk::new(),
+            fragments: 0,
+            adj_chunks: 0,
+            chunk_info: [0; CHUNK_SIZE * CHUNK_SIZE / 32],
+            state: ChunkState::Unmeshed,
+            biomes: None,
+        }
+    }
 }
 
 /// A client chunk's state
@@ -209,52 +239,19 @@ impl InputImpl {
             factory.create_sampler(SamplerInfo::new(FilterMethod::Scale, WrapMode::Clamp));
 
         // Blocks
-        let (atlas, texture_registry) = load_textures(&mut factory);
-        let air = create_block_air();
-        let dirt = create_block_cube(["dirt"; 6], &texture_registry);
-        let grass = create_block_cube(
-            [
-                "grass_side",
-                "grass_side",
-                "grass_side",
-                "grass_side",
-                "grass_top",
-                "dirt",
-            ],
-            &texture_registry,
-        );
-        let wood = create_block_cube(
-            [
-                "wood_side",
-                "wood_side",
-                "wood_side",
-                "wood_side",
-                "wood_top",
-                "wood_top",
-            ],
-            &texture_registry,
-        );
-        let leaves = create_block_cube(["leaves"; 6], &texture_registry);
-        let stone = create_block_cube(["stone"; 6], &texture_registry);
-        let coal = create_block_cube(["ore_coal"; 6], &texture_registry);
-
-        let mut br = BlockRegistry::new();
-        br.add_block(Box::new(air));
-        br.add_block(Box::new(dirt));
-        br.add_block(Box::new(grass));
-        br.add_block(Box::new(wood));
-        br.add_block(Box::new(leaves));
-        br.add_block(Box::new(stone));
-        br.add_block(Box::new(coal));
-
-        let br = Arc::new(br);
+        let block_definitions = load_block_definitions(Path::new("assets/blocks.toml"));
+        let (atlas, texture_registry) =
+            load_textures(&mut factory, &block_texture_names(&block_definitions));
+        let br = Arc::new(create_block_registry(&block_definitions, &texture_registry));
 
         // Channels
         let rx;
         let meshing_tx;
         let network_tx;
+        let mut server_handle = None;
         // Start threads
         {
+            use crate::network::tcp::TcpClient;
             use crate::simple::network::{SimpleClient, SimpleServer};
             // Input
             let (input_t, input_r) = channel();
@@ -262,11 +259,6 @@ impl InputImpl {
             let (meshing_t, meshing_r) = channel();
             // Network
             let (network_t, network_r) = channel();
-            // Client-server
-            let (to_server, from_client) = channel();
-            let (to_client, from_server) = channel();
-            let client = SimpleClient::new(from_server, to_server);
-            let server = SimpleServer::new(from_client, to_client);
             {
                 let input_tx = input_t.clone();
                 let br2 = br.clone();
@@ -276,30 +268,41 @@ impl InputImpl {
                 println!("Started meshing thread");
             }
 
-            {
+            if config.singleplayer {
+                // Client-server
+                let (to_server, from_client) = channel();
+                let (to_client, from_server) = channel();
+                let client = SimpleClient::new(from_server, to_server);
+                let server = SimpleServer::new(from_client, to_client);
+
                 let input_tx = input_t.clone();
+                let br2 = br.clone();
+                let player_name = config.player_name.clone();
                 thread::spawn(move || {
-                    crate::client::network::start(network_r, input_tx, client);
+                    crate::client::network::start(network_r, input_tx, client, br2, player_name);
                 });
                 println!("Started network thread");
-            }
 
-            {
-                let (game_tx, game_rx) = channel();
-                let (network_tx, network_rx) = channel();
-                let (worldgen_tx, worldgen_rx) = channel();
-                let game_t = game_tx.clone();
-                thread::spawn(move || {
-                    crate::server::network::start(network_rx, game_t, server);
-                });
-                let config = config.clone();
-                thread::spawn(move || {
-                    crate::server::game::start(game_rx, network_tx, worldgen_tx, config);
+                let mut server_config = (*config).clone();
+                server_config.allow_flying = true;
+                server_handle = Some(crate::server::start(
+                    server,
+                    Path::new(&config.world_path),
+                    Arc::new(server_config),
+                    br.clone(),
+                ));
+            } else {
+                let client = TcpClient::connect(&config.server_address[..]).unwrap_or_else(|e| {
+                    panic!("Failed to connect to {}: {}", config.server_address, e)
                 });
 
+                let input_tx = input_t.clone();
+                let br2 = br.clone();
+                let player_name = config.player_name.clone();
                 thread::spawn(move || {
-                    crate::server::worldgen::start(worldgen_rx, game_tx);
+                    crate::client::network::start(network_r, input_tx, client, br2, player_name);
                 });
+                println!("Started network thread");
             }
 
             rx = input_r;
@@ -333,7 +336,7 @@ impl InputImpl {
 
         window.set_cursor(MouseCursor::Crosshair);
 
-        // Send render distance
+        // Send render distance. The movement mode is restored by the server.
         network_tx
             .send(ToNetwork::SetRenderDistance(config.render_distance as u64))
             .unwrap();
@@ -346,6 +349,7 @@ impl InputImpl {
             pending_messages: VecDeque::new(),
             meshing_tx,
             network_tx,
+            server: server_handle,
             input_state: InputState {
                 window,
                 focused: false,
@@ -353,6 +357,10 @@ impl InputImpl {
                 keyboard_state: KeyboardState::new(),
                 camera: cam,
                 timer: Instant::now(),
+                selected_block: br
+                    .get_id("dirt")
+                    .expect("there is no dirt block in assets/blocks.toml"),
+                movement_mode: config.movement_mode,
             },
             game_state: ClientGameState {
                 chunks: HashMap::new(),
@@ -1,4 +1,4 @@
-//! The meshing thread computes chunk meshes from `ChunkArray`s.
+//! The meshing thread computes chunk meshes from `Chunk`s.
 //! It it used to offload computation-intensive operations from the input thread.
 
 use crate::{
@@ -80,7 +80,7 @@ impl MeshingImpl {
                         if 0 <= x && x < sz && 0 <= y && y < sz && 0 <= z && z < sz {
                             if !self
                                 .block_registry
-                                .get_block(blocks[x as usize][y as usize][z as usize])
+                                .get_block(blocks.get(x as usize, y as usize, z as usize))
                                 .is_opaque()
                             {
                                 chunk.sides[i as usize][j as usize][k as usize] |= 1 << side;
@@ -1,18 +1,28 @@
 //! The network thread manages client-server interaction.
 
+use crate::block::{BlockIdMap, BlockRegistry};
 use crate::core::messages::client::{ToInput, ToNetwork};
 use crate::core::messages::network::{ToClient, ToServer};
-use crate::network::{deserialize_fragment, Client, ClientEvent};
+use crate::network::{
+    deserialize_biomes, deserialize_fragment, Client, ClientEvent, PROTOCOL_VERSION,
+};
 use std::collections::VecDeque;
 use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::sync::Arc;
 
-pub fn start<C>(client_rx: Receiver<ToNetwork>, input_tx: Sender<ToInput>, client: C)
-where
+pub fn start<C>(
+    client_rx: Receiver<ToNetwork>,
+    input_tx: Sender<ToInput>,
+    client: C,
+    block_registry: Arc<BlockRegistry>,
+    player_name: String,
+) where
     C: Client,
 {
-    let mut implementation = ClientImpl::from_parts(client_rx, input_tx, client);
+    let mut implementation =
+        ClientImpl::from_parts(client_rx, input_tx, client, block_registry, player_name);
 
-    loop {
+    while implementation.running {
         implementation.send_messages();
 
         implementation.receive_messages();
@@ -27,6 +37,12 @@ where
     input_tx: Sender<ToInput>,
     client: C,
     pending_messages: VecDeque<ToNetwork>,
+    block_registry: Arc<BlockRegistry>,
+    /// Sent to the server after the handshake
+    player_name: String,
+    /// Conversion between the server's block ids and ours, known after the handshake
+    id_map: Option<BlockIdMap>,
+    running: bool,
 }
 
 impl<C> ClientImpl<C>
@@ -37,15 +53,28 @@ where
         client_rx: Receiver<ToNetwork>,
         input_tx: Sender<ToInput>,
         client: C,
+        block_registry: Arc<BlockRegistry>,
+        player_name: String,
     ) -> Self {
         ClientImpl {
             client_rx,
             input_tx,
             client,
             pending_messages: VecDeque::new(),
+            block_registry,
+            player_name,
+            id_map: None,
+            running: true,
         }
     }
 
+    /// Stop the network thread, and let the input thread know why
+    fn disconnect(&mut self, reason: String) {
+        // The input thread may have stopped already
+        let _ = self.input_tx.send(ToInput::Disconnect(reason));
+        self.running = false;
+    }
+
     pub fn send_messages(&mut self) {
         loop {
             match self.client_rx.try_recv() {
@@ -53,18 +82,34 @@ where
                     self.pending_messages.push_back(message);
                 }
                 Err(kind) => match kind {
-                    // TODO: something better than panicking
-                    TryRecvError::Disconnected => panic!("Network thread disconnected"),
+                    // The input thread stopped
+                    TryRecvError::Disconnected => {
+                        self.running = false;
+                        return;
+                    }
                     TryRecvError::Empty => break,
                 },
             }
         }
+        // Nothing can be sent before logging in, which happens after the handshake
+        if self.id_map.is_none() {
+            return;
+        }
         while let Some(message) = self.pending_messages.pop_front() {
             let message = match message {
                 ToNetwork::SetInput(input) => ToServer::SetInput(input),
                 ToNetwork::SetRenderDistance(render_distance) => {
                     ToServer::SetRenderDistance(render_distance)
                 }
+                ToNetwork::SetMovementMode(mode) => ToServer::SetMovementMode(mode),
+                ToNetwork::BreakBlock(pos) => ToServer::BreakBlock(pos),
+                ToNetwork::PlaceBlock(pos, block) => {
+                    // Blocks the server doesn't know can't be placed
+                    match self.id_map.as_ref().and_then(|m| m.to_remote(block)) {
+                        Some(block) => ToServer::PlaceBlock(pos, block),
+                        None => continue,
+                    }
+                }
             };
             self.client
                 .send_message(bincode::serialize(&message).unwrap());
@@ -72,21 +117,90 @@ where
     }
 
     pub fn receive_messages(&mut self) {
-        while let Some(event) = self.client.next_event() {
+        while self.running {
+            let event = match self.client.next_event() {
+                Some(event) => event,
+                None => break,
+            };
             match event {
                 ClientEvent::Connection => (),
-                ClientEvent::ConnectionClosed => panic!("Connection closed"),
+                ClientEvent::ConnectionClosed => {
+                    self.disconnect("the connection was closed".to_owned());
+                }
                 ClientEvent::Message(msg) => {
                     //println!("Network: received event {:?}", message);
-                    match bincode::deserialize(msg.as_ref()).unwrap() {
+                    let message: ToClient = match bincode::deserialize(msg.as_ref()) {
+                        Ok(message) => message,
+                        Err(_) => {
+                            self.disconnect("the server sent an invalid message".to_owned());
+                            break;
+                        }
+                    };
+                    let id_map = if let ToClient::Handshake { .. } = message {
+                        None
+                    } else {
+                        match self.id_map {
+                            Some(ref id_map) => Some(id_map),
+                            None => {
+                                self.disconnect("the server didn't send a handshake".to_owned());
+                                break;
+                            }
+                        }
+                    };
+                    match message {
+                        ToClient::Handshake {
+                            protocol_version,
+                            blocks,
+                        } => {
+                            if protocol_version != PROTOCOL_VERSION {
+                                self.disconnect(format!(
+                                    "the server uses protocol version {}, but the client uses version {}",
+                                    protocol_version, PROTOCOL_VERSION
+                                ));
+                                break;
+                            }
+                            let id_map = BlockIdMap::new(&blocks, &self.block_registry);
+                            if !id_map.missing.is_empty() {
+                                self.disconnect(format!(
+                                    "the server has blocks that the client doesn't know: {}",
+                                    id_map.missing.join(", ")
+                                ));
+                                break;
+                            }
+                            self.id_map = Some(id_map);
+                            let login = ToServer::Login(self.player_name.clone());
+                            self.client
+                                .send_message(bincode::serialize(&login).unwrap());
+                        }
+                        ToClient::NewChunk(pos, biomes) => {
+                            let biomes = match deserialize_biomes(&biomes) {
+                                Some(biomes) => biomes,
+                                None => {
+                                    self.disconnect("the server sent invalid biomes".to_owned());
+                                    break;
+                                }
+                            };
+                            self.input_tx.send(ToInput::NewChunk(pos)).unwrap();
+                            self.input_tx
+                                .send(ToInput::ChunkBiomes(pos, biomes))
+                                .unwrap();
+                        }
                         ToClient::NewChunkFragment(pos, fpos, frag) => {
                             //println!("Network: received chunk fragment @ {:?}, {:?}", pos, fpos);
+                            let mut frag = match deserialize_fragment(&frag[..]) {
+                                Some(frag) => frag,
+                                None => {
+                                    self.disconnect(
+                                        "the server sent an invalid chunk fragment".to_owned(),
+                                    );
+                                    break;
+                                }
+                            };
+                            for block in frag.iter_mut() {
+                                *block = id_map.unwrap().to_local(*block);
+                            }
                             self.input_tx
-                                .send(ToInput::NewChunkFragment(
-                                    pos,
-                                    fpos,
-                                    deserialize_fragment(&frag[..]),
-                                ))
+                                .send(ToInput::NewChunkFragment(pos, fpos, frag))
                                 .unwrap();
                         }
                         ToClient::NewChunkInfo(pos, info) => {
@@ -95,9 +209,27 @@ where
                                 .send(ToInput::NewChunkInfo(pos, info))
                                 .unwrap();
                         }
+                        ToClient::BlockUpdate(pos, block) => {
+                            let block = id_map.unwrap().to_local(block);
+                            self.input_tx
+                                .send(ToInput::BlockUpdate(pos, block))
+                                .unwrap();
+                        }
                         ToClient::SetPos(pos) => {
                             self.input_tx.send(ToInput::SetPos(pos)).unwrap();
                         }
+                        ToClient::SetMovementMode(mode) => {
+                            self.input_tx.send(ToInput::SetMovementMode(mode)).unwrap();
+                        }
+                        ToClient::Spawn(record) => {
+                            self.input_tx.send(ToInput::Spawn(record)).unwrap();
+                        }
+                        ToClient::Disconnect(reason) => {
+                            self.disconnect(format!(
+                                "the server refused the connection: {}",
+                                reason
+                            ));
+                        }
                     }
                 }
             }
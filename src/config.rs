@@ -1,19 +1,17 @@
 //! Configuration.
 
+use crate::player::MovementMode;
 use serde_derive::{Deserialize, Serialize};
 use std::fs::OpenOptions;
 use std::io::{Read, Write};
 use std::path::Path;
 
 /// Temporary way to configure the engine
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
-    #[serde(default = "default_player_x")]
-    pub player_x: f64,
-    #[serde(default = "default_player_y")]
-    pub player_y: f64,
-    #[serde(default = "default_player_z")]
-    pub player_z: f64,
+    /// Name used to log in, which identifies the player on the server
+    #[serde(default = "default_player_name")]
+    pub player_name: String,
     #[serde(default = "default_mouse_speed")]
     pub mouse_speed: f64,
     #[serde(default = "default_player_speed")]
@@ -24,18 +22,49 @@ pub struct Config {
     pub render_distance: i64,
     #[serde(default = "default_tick_rate")]
     pub tick_rate: u64,
+    /// World of the integrated server, and default world of the dedicated server
+    #[serde(default = "default_world_path")]
+    pub world_path: String,
+    /// Seed of new worlds. A random seed is picked if there is none.
+    #[serde(default)]
+    pub world_seed: Option<u64>,
+    /// Generator of new worlds, see `server::worldgen::GENERATORS`
+    #[serde(default = "default_world_generator")]
+    pub world_generator: String,
+    /// Layers of the flat generator, from the bottom to the top, e.g. `stone,3*dirt,grass`
+    #[serde(default = "default_flat_layers")]
+    pub flat_layers: String,
+    /// Number of octaves of the 3D noise of the default generator
+    #[serde(default = "default_terrain_octaves")]
+    pub terrain_octaves: usize,
+    /// Strength, in blocks, of the domain warping of the 3D noise of the default generator
+    #[serde(default = "default_terrain_warp")]
+    pub terrain_warp: f64,
+    /// Seconds between two saves of the world
+    #[serde(default = "default_autosave_interval")]
+    pub autosave_interval: u64,
+    /// Number of threads generating chunks
+    #[serde(default = "default_worldgen_threads")]
+    pub worldgen_threads: usize,
+    /// Number of columns of chunks whose terrain and caves are cached by each worldgen thread
+    #[serde(default = "default_worldgen_column_cache")]
+    pub worldgen_column_cache: usize,
+    /// Start a server in the same process instead of connecting to `server_address`
+    #[serde(default = "default_singleplayer")]
+    pub singleplayer: bool,
+    #[serde(default = "default_server_address")]
+    pub server_address: String,
+    /// Movement mode of new players
+    #[serde(default = "default_movement_mode")]
+    pub movement_mode: MovementMode,
+    /// Whether the players of the server may fly and go through blocks, instead of only walking.
+    /// The player of a singleplayer game always may.
+    #[serde(default)]
+    pub allow_flying: bool,
 }
 
-fn default_player_x() -> f64 {
-    0.0
-}
-
-fn default_player_y() -> f64 {
-    -100.0
-}
-
-fn default_player_z() -> f64 {
-    0.0
+fn default_player_name() -> String {
+    "player".to_owned()
 }
 
 fn default_mouse_speed() -> f64 {
@@ -58,6 +87,50 @@ fn default_tick_rate() -> u64 {
     2500
 }
 
+fn default_world_path() -> String {
+    "world".to_owned()
+}
+
+fn default_world_generator() -> String {
+    "default".to_owned()
+}
+
+fn default_flat_layers() -> String {
+    "stone,3*dirt,grass".to_owned()
+}
+
+fn default_terrain_octaves() -> usize {
+    4
+}
+
+fn default_terrain_warp() -> f64 {
+    16.0
+}
+
+fn default_autosave_interval() -> u64 {
+    60
+}
+
+fn default_worldgen_threads() -> usize {
+    2
+}
+
+fn default_worldgen_column_cache() -> usize {
+    256
+}
+
+fn default_singleplayer() -> bool {
+    true
+}
+
+fn default_server_address() -> String {
+    "127.0.0.1:1106".to_owned()
+}
+
+fn default_movement_mode() -> MovementMode {
+    MovementMode::Fly
+}
+
 pub fn load_config(path: &Path) -> Config {
     let mut config_file = OpenOptions::new()
         .read(true)
@@ -3,20 +3,36 @@
 pub mod messages {
     /// Client-to-client messages.
     pub mod client {
-        use crate::block::{Chunk, ChunkFragment, ChunkInfo, ChunkPos, FragmentPos};
-        use crate::player::{PlayerInput, PlayerPos};
+        use crate::biome::ChunkBiomes;
+        use crate::block::{
+            BlockId, BlockPos, Chunk, ChunkFragment, ChunkInfo, ChunkPos, FragmentPos,
+        };
+        use crate::player::{MovementMode, PlayerInput, PlayerPos, PlayerRecord};
         use crate::Vertex;
 
         pub enum ToNetwork {
             SetInput(PlayerInput),
             SetRenderDistance(u64),
+            SetMovementMode(MovementMode),
+            BreakBlock(BlockPos),
+            PlaceBlock(BlockPos, BlockId),
         }
 
         pub enum ToInput {
             NewChunkBuffer(ChunkPos, Vec<Vertex>),
+            /// The fragments of the chunk are about to be received, and replace the old ones
+            NewChunk(ChunkPos),
             NewChunkFragment(ChunkPos, FragmentPos, Box<ChunkFragment>),
             NewChunkInfo(ChunkPos, ChunkInfo),
+            ChunkBiomes(ChunkPos, Box<ChunkBiomes>),
+            BlockUpdate(BlockPos, BlockId),
             SetPos(PlayerPos),
+            /// The server changed the movement mode of the player
+            SetMovementMode(MovementMode),
+            /// The player was restored by the server
+            Spawn(PlayerRecord),
+            /// The connection was refused or lost, for the given reason
+            Disconnect(String),
         }
 
         pub enum ToMeshing {
@@ -26,51 +42,102 @@ pub mod messages {
 
     /// Client-to-server and server-to-client messages.
     pub mod network {
-        use crate::block::{ChunkInfo, ChunkPos, FragmentPos};
-        use crate::player::{PlayerInput, PlayerPos};
+        use crate::block::{BlockId, BlockPos, BlockTable, ChunkInfo, ChunkPos, FragmentPos};
+        use crate::player::{MovementMode, PlayerInput, PlayerPos, PlayerRecord};
         use serde_derive::{Deserialize, Serialize};
 
         #[derive(Serialize, Deserialize)]
         pub enum ToClient {
+            /// First message of every connection. It must remain the first variant so that
+            /// clients using another protocol version can still read it.
+            Handshake {
+                protocol_version: u32,
+                blocks: BlockTable,
+            },
+            /// Sent before the fragments of a chunk, with the biomes of its columns encoded by
+            /// `serialize_biomes`. The client forgets what it received of the chunk before, since
+            /// chunks are sent again when they change.
+            NewChunk(ChunkPos, Vec<u8>),
             NewChunkFragment(ChunkPos, FragmentPos, Vec<u8>),
             NewChunkInfo(ChunkPos, ChunkInfo),
+            BlockUpdate(BlockPos, BlockId),
             SetPos(PlayerPos),
+            /// Sent when the server refuses the movement mode the player asked for
+            SetMovementMode(MovementMode),
+            /// Sent once the player has logged in, with the position, orientation and movement mode
+            /// of their last session
+            Spawn(PlayerRecord),
+            /// The server refused the connection, for the given reason
+            Disconnect(String),
         }
 
         #[derive(Serialize, Deserialize)]
         pub enum ToServer {
+            /// First message of every connection, sent after the handshake. The server ignores
+            /// the other messages until the player has logged in.
+            Login(String),
             SetInput(PlayerInput),
             SetRenderDistance(u64),
+            SetMovementMode(MovementMode),
+            BreakBlock(BlockPos),
+            PlaceBlock(BlockPos, BlockId),
         }
     }
 
     /// Server-to-server messages.
     pub mod server {
-        use crate::block::{ChunkArray, ChunkPos};
+        use crate::biome::ChunkBiomes;
+        use crate::block::{BlockId, BlockPos, BlockRegion, ChunkPos, PalettedChunk};
         use crate::network::ConnectionId;
-        use crate::player::{PlayerInput, PlayerPos};
+        use crate::player::{MovementMode, PlayerInput, PlayerPos, PlayerRecord};
+        use std::sync::mpsc::Sender;
 
         pub enum ToNetwork {
-            NewChunk(ConnectionId, ChunkPos, Box<ChunkArray>),
+            NewChunk(ConnectionId, ChunkPos, PalettedChunk, Box<ChunkBiomes>),
+            BlockUpdate(ConnectionId, BlockPos, BlockId),
             SetPos(ConnectionId, PlayerPos),
+            SetMovementMode(ConnectionId, MovementMode),
+            Spawn(ConnectionId, PlayerRecord),
+            /// Refuse the connection of a player, for the given reason
+            Disconnect(ConnectionId, String),
+            /// Stop the network thread
+            Shutdown,
         }
 
         #[derive(Debug)]
         pub enum ToGame {
             PlayerEvent(ConnectionId, ToGamePlayer),
-            NewChunk(ChunkPos, Box<ChunkArray>),
+            NewChunk(ChunkPos, PalettedChunk, Box<ChunkBiomes>),
+            /// Copy the blocks of the region of the given size whose lowest corner is at the given
+            /// position
+            CaptureRegion(BlockPos, [usize; 3], Sender<Result<BlockRegion, String>>),
+            /// Replace the blocks of a region whose lowest corner is at the given position, except
+            /// where the region has air if the flag is set
+            PasteRegion(BlockPos, BlockRegion, bool, Sender<Result<(), String>>),
+            /// Save the world and stop the server threads
+            Shutdown,
         }
 
         #[derive(Debug)]
         pub enum ToGamePlayer {
             Connect,
+            Login(String),
             SetInput(PlayerInput),
             SetRenderDistance(u64),
+            SetMovementMode(MovementMode),
+            BreakBlock(BlockPos),
+            PlaceBlock(BlockPos, BlockId),
             Disconnect,
         }
 
         pub enum ToWorldgen {
             GenerateChunk(ChunkPos),
+            /// The chunk is no longer needed. It is only generated if it is requested again.
+            CancelChunk(ChunkPos),
+            /// Chunks the players are in, used to generate the closest chunks first
+            SetPlayerChunks(Vec<ChunkPos>),
+            /// Stop the worldgen threads, dropping the pending chunks
+            Shutdown,
         }
     }
 }
@@ -0,0 +1,60 @@
+//! Rust voxel engine and game.
+//! The `voxel-rs` binary starts the client (and an integrated server), and the `voxel-server`
+//! binary starts a dedicated server.
+
+#[macro_use]
+extern crate gfx;
+
+// MUST BE A MULTIPLE OF 8 !
+pub const CHUNK_SIZE: usize = 32;
+
+// TODO: refactor ?
+pub type ColorFormat = gfx::format::Srgba8;
+pub type DepthFormat = gfx::format::DepthStencil;
+
+gfx_defines! {
+    vertex Vertex {
+        pos: [f32; 4] = "a_Pos",
+        uv: [f32; 2] = "a_Uv",
+        normal: [f32; 3] = "a_Normal",
+    }
+
+    constant Transform {
+        view_proj: [[f32; 4]; 4] = "u_ViewProj",
+        model: [[f32; 4]; 4] = "u_Model",
+    }
+
+    constant PlayerData {
+        direction: [f32; 3] = "u_Direction",
+    }
+
+    pipeline pipe {
+        vbuf: gfx::VertexBuffer<Vertex> = (),
+        transform: gfx::ConstantBuffer<Transform> = "Transform",
+        player_data: gfx::ConstantBuffer<PlayerData> = "PlayerData",
+        image: gfx::TextureSampler<[f32; 4]> = "t_Image",
+        out_color: gfx::RenderTarget<ColorFormat> = "Target0",
+        out_depth: gfx::DepthTarget<DepthFormat> =
+            gfx::preset::depth::LESS_EQUAL_WRITE,
+    }
+}
+
+pub mod biome;
+pub mod block;
+pub mod client;
+pub mod config;
+pub mod core;
+pub mod input;
+pub mod network;
+pub mod physics;
+pub mod player;
+pub mod raycast;
+pub mod region;
+pub mod render;
+pub mod schematic;
+pub mod server;
+pub mod simple;
+pub mod texture;
+pub mod util;
+pub mod vox;
+pub mod world;
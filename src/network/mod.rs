@@ -1,9 +1,17 @@
 //! Various network-related utilities.
-//! For now this means `ChunkFragment` serialization and deserialization.
+//! For now this means the `Server` and `Client` traits, their TCP implementation in the `tcp`
+//! submodule, and `ChunkFragment` and `ChunkBiomes` serialization and deserialization.
 
+use crate::biome::{ChunkBiomes, BIOMES};
 use crate::block::{BlockId, ChunkFragment};
 use crate::CHUNK_SIZE;
 
+pub mod tcp;
+
+/// Version of the client-server protocol. Clients refuse to connect to servers using another
+/// version.
+pub const PROTOCOL_VERSION: u32 = 5;
+
 /// A client-side network event
 pub enum ClientEvent {
     /// Connection with the server established.
@@ -31,6 +39,8 @@ pub trait Server {
     fn next_event(&mut self) -> Option<ServerEvent>;
     /// Send a message.
     fn send_message(&mut self, connection: ConnectionId, message: Vec<u8>);
+    /// Close a connection. It is followed by a `ConnectionClosed` event.
+    fn disconnect(&mut self, connection: ConnectionId);
 }
 
 pub trait Client {
@@ -40,7 +50,8 @@ pub trait Client {
     fn send_message(&mut self, message: Vec<u8>);
 }
 
-fn serialize_blocks(blocks: &[BlockId]) -> Vec<u8> {
+/// Run-length encode a list of blocks
+pub fn serialize_blocks(blocks: &[BlockId]) -> Vec<u8> {
     fn encode(out: &mut Vec<u8>, current_block: BlockId, mut count: u8) {
         if count == 0 {
             return;
@@ -62,8 +73,9 @@ fn serialize_blocks(blocks: &[BlockId]) -> Vec<u8> {
     }
     let mut current_block = blocks[0];
     let mut count: u8 = 1;
-    for &id in blocks.split_at(0).1.iter() {
-        if id == current_block && count < 255 {
+    for &id in blocks.split_at(1).1.iter() {
+        // The count is stored on 7 bits
+        if id == current_block && count < 127 {
             count += 1;
         } else {
             encode(&mut out, current_block, count);
@@ -75,7 +87,8 @@ fn serialize_blocks(blocks: &[BlockId]) -> Vec<u8> {
     out
 }
 
-fn deserialize_blocks(bytes: &[u8]) -> Vec<BlockId> {
+/// Decode a list of blocks encoded by `serialize_blocks`, `None` if the bytes are truncated
+pub fn deserialize_blocks(bytes: &[u8]) -> Option<Vec<BlockId>> {
     let mut out = Vec::new();
     let mut it = bytes.iter();
 
@@ -85,33 +98,111 @@ fn deserialize_blocks(bytes: &[u8]) -> Vec<BlockId> {
         let mut count = 1;
         if b & (1 << 7) > 0 {
             count = b ^ (1 << 7);
-            match it.next() {
-                Some(x) => b = *x,
-                None => unreachable!(),
-            }
+            b = *it.next()?;
         }
         let first_half = b as u16;
-        let second_half = match it.next() {
-            Some(x) => *x,
-            None => unreachable!(),
-        } as u16;
+        let second_half = *it.next()? as u16;
         for _ in 0..count {
             out.push(BlockId::from(first_half * (1 << 8) + second_half));
         }
     }
 
-    out
+    Some(out)
 }
 
 pub fn serialize_fragment(frag: &ChunkFragment) -> Vec<u8> {
     serialize_blocks(&frag[..])
 }
 
-pub fn deserialize_fragment(bytes: &[u8]) -> Box<ChunkFragment> {
+/// Decode a fragment encoded by `serialize_fragment`, `None` if the bytes are truncated
+pub fn deserialize_fragment(bytes: &[u8]) -> Option<Box<ChunkFragment>> {
     let mut frag = Box::new([BlockId::from(0); CHUNK_SIZE]);
-    let blocks = deserialize_blocks(bytes);
+    let blocks = deserialize_blocks(bytes)?;
     for (f, b) in frag.iter_mut().zip(blocks.iter()) {
         *f = *b;
     }
-    frag
+    Some(frag)
+}
+
+/// Run-length encode the biomes of a chunk, as pairs of a count and an index into `BIOMES`
+pub fn serialize_biomes(biomes: &ChunkBiomes) -> Vec<u8> {
+    let mut out = Vec::new();
+    for &biome in biomes.iter().flatten() {
+        let index = BIOMES.iter().position(|&b| b == biome).unwrap() as u8;
+        let len = out.len();
+        if len > 0 && out[len - 1] == index && out[len - 2] < u8::max_value() {
+            out[len - 2] += 1;
+        } else {
+            out.push(1);
+            out.push(index);
+        }
+    }
+    out
+}
+
+/// Decode the biomes encoded by `serialize_biomes`, `None` if the bytes don't hold the biomes of
+/// a chunk
+pub fn deserialize_biomes(bytes: &[u8]) -> Option<Box<ChunkBiomes>> {
+    let mut biomes = Box::new([[BIOMES[0]; CHUNK_SIZE]; CHUNK_SIZE]);
+    let mut columns = biomes.iter_mut().flat_map(|row| row.iter_mut());
+    for run in bytes.chunks(2) {
+        if run.len() != 2 {
+            return None;
+        }
+        let biome = *BIOMES.get(run[1] as usize)?;
+        for _ in 0..run[0] {
+            *columns.next()? = biome;
+        }
+    }
+    if columns.next().is_some() {
+        return None;
+    }
+    Some(biomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_blocks() {
+        let blocks: Vec<_> = [1, 1, 1, 300, 2]
+            .iter()
+            .map(|&id| BlockId::from(id))
+            .collect();
+        let bytes = serialize_blocks(&blocks);
+        assert_eq!(deserialize_blocks(&bytes), Some(blocks));
+        for len in 0..bytes.len() {
+            // Cutting between two runs gives fewer blocks instead
+            if let Some(blocks) = deserialize_blocks(&bytes[..len]) {
+                assert!(blocks.len() < 5);
+            }
+        }
+        assert_eq!(deserialize_blocks(&bytes[..1]), None);
+    }
+
+    #[test]
+    fn biomes_round_trip() {
+        let mut biomes = Box::new([[BIOMES[0]; CHUNK_SIZE]; CHUNK_SIZE]);
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                if x + z > CHUNK_SIZE {
+                    biomes[x][z] = BIOMES[x % BIOMES.len()];
+                }
+            }
+        }
+        let bytes = serialize_biomes(&biomes);
+        assert!(bytes.len() < CHUNK_SIZE * CHUNK_SIZE);
+        assert_eq!(deserialize_biomes(&bytes), Some(biomes));
+        // Missing columns, unknown biomes and extra columns
+        assert_eq!(deserialize_biomes(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(deserialize_biomes(&bytes[..bytes.len() - 2]), None);
+        assert_eq!(
+            deserialize_biomes(&[255, 0, 255, 0, 255, 0, 255, 0, 4, 9]),
+            None
+        );
+        let mut extra = bytes.clone();
+        extra.extend_from_slice(&[1, 0]);
+        assert_eq!(deserialize_biomes(&extra), None);
+    }
 }
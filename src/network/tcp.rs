@@ -0,0 +1,375 @@
+//! TCP implementation of the `Server` and `Client` traits.
+//! Every message is framed by its length, stored as a little-endian `u32`. Empty frames are
+//! keep-alive messages: they are sent when nothing else was sent for `KEEP_ALIVE_INTERVAL`, and
+//! a connection that received nothing for `TIMEOUT` is considered lost.
+//! Every connection has a reader thread and a writer thread, so that sending never blocks.
+
+use super::{Client, ClientEvent, ConnectionId, Server, ServerEvent};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{
+    channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError,
+    TrySendError,
+};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(1);
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Larger frames are considered to be garbage and close the connection
+const MAX_MESSAGE_SIZE: usize = 1 << 24;
+/// Connections with more frames waiting to be sent are too slow, and are closed
+const MAX_QUEUED_FRAMES: usize = 1 << 14;
+
+/// Write a length-prefixed frame
+fn write_frame(stream: &mut TcpStream, message: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(message.len() + 4);
+    frame.extend_from_slice(&(message.len() as u32).to_le_bytes());
+    frame.extend_from_slice(message);
+    stream.write_all(&frame)
+}
+
+/// Read a length-prefixed frame
+fn read_frame(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame too large ({} bytes)", len),
+        ));
+    }
+    let mut message = vec![0; len];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+/// Configure a freshly opened stream and clone it for the reader and writer threads
+fn setup_stream(stream: &TcpStream) -> io::Result<(TcpStream, Writer)> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    Ok((stream.try_clone()?, Writer::new(stream)?))
+}
+
+/// Read frames until the connection is closed or lost, calling `on_message` for every
+/// non-empty frame
+fn read_frames(mut stream: TcpStream, mut on_message: impl FnMut(Vec<u8>) -> bool) {
+    loop {
+        match read_frame(&mut stream) {
+            Ok(ref message) if message.is_empty() => (),
+            Ok(message) => {
+                if !on_message(message) {
+                    break;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+/// Write the frames sent by `Writer`, and keep-alive frames when there are none, until the
+/// `Writer` is dropped or the connection is lost
+fn write_frames(mut stream: TcpStream, frames: Receiver<Vec<u8>>) {
+    loop {
+        let frame = match frames.recv_timeout(KEEP_ALIVE_INTERVAL) {
+            Ok(frame) => frame,
+            Err(RecvTimeoutError::Timeout) => Vec::new(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if write_frame(&mut stream, &frame).is_err() {
+            break;
+        }
+    }
+    // The reader thread will notice and close the connection
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+/// Sending half of a connection. The frames are queued for a writer thread. Dropping the
+/// `Writer` closes the connection once the queued frames are sent.
+struct Writer {
+    frames: SyncSender<Vec<u8>>,
+    stream: TcpStream,
+}
+
+impl Writer {
+    fn new(stream: &TcpStream) -> io::Result<Self> {
+        let (frames, rx) = sync_channel(MAX_QUEUED_FRAMES);
+        let writer_stream = stream.try_clone()?;
+        thread::spawn(move || write_frames(writer_stream, rx));
+        Ok(Self {
+            frames,
+            stream: stream.try_clone()?,
+        })
+    }
+
+    fn send(&self, message: Vec<u8>) {
+        match self.frames.try_send(message) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                // The reader thread will notice and close the connection
+                println!("WARNING: closing a connection that doesn't receive its messages");
+                self.shutdown();
+            }
+            // The writer thread stopped because the connection was lost
+            Err(TrySendError::Disconnected(_)) => (),
+        }
+    }
+
+    /// Close the connection right away
+    fn shutdown(&self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+pub struct TcpServer {
+    events: Receiver<ServerEvent>,
+    connections: Arc<Mutex<HashMap<ConnectionId, Writer>>>,
+    stopped: Arc<AtomicBool>,
+    addr: SocketAddr,
+}
+
+impl TcpServer {
+    /// Listen on `addr` and accept connections in a background thread
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let addr = resolve(addr)?;
+        let builder = match addr {
+            SocketAddr::V4(_) => net2::TcpBuilder::new_v4()?,
+            SocketAddr::V6(_) => net2::TcpBuilder::new_v6()?,
+        };
+        let listener = builder.reuse_address(true)?.bind(addr)?.listen(128)?;
+        let addr = listener.local_addr()?;
+        // Non-blocking so that the accepting thread can notice when the server is dropped
+        listener.set_nonblocking(true)?;
+
+        let (events_tx, events) = channel();
+        let connections = Arc::new(Mutex::new(HashMap::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        {
+            let connections = connections.clone();
+            let stopped = stopped.clone();
+            thread::spawn(move || accept_connections(listener, events_tx, connections, stopped));
+        }
+
+        Ok(Self {
+            events,
+            connections,
+            stopped,
+            addr,
+        })
+    }
+
+    /// Address the server listens on, useful when it was bound to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+fn accept_connections(
+    listener: TcpListener,
+    events_tx: Sender<ServerEvent>,
+    connections: Arc<Mutex<HashMap<ConnectionId, Writer>>>,
+    stopped: Arc<AtomicBool>,
+) {
+    let mut next_id: ConnectionId = 0;
+    while !stopped.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+            Err(e) => {
+                println!("WARNING: failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let (reader, writer) = match stream
+            .set_nonblocking(false)
+            .and_then(|_| setup_stream(&stream))
+        {
+            Ok(streams) => streams,
+            Err(e) => {
+                println!("WARNING: failed to set up connection: {}", e);
+                continue;
+            }
+        };
+
+        let id = next_id;
+        next_id += 1;
+        connections.lock().unwrap().insert(id, writer);
+        if events_tx.send(ServerEvent::Connection(id)).is_err() {
+            break;
+        }
+        let events_tx = events_tx.clone();
+        thread::spawn(move || {
+            read_frames(reader, |message| {
+                events_tx.send(ServerEvent::Message(id, message)).is_ok()
+            });
+            let _ = events_tx.send(ServerEvent::ConnectionClosed(id));
+        });
+    }
+}
+
+impl Server for TcpServer {
+    fn next_event(&mut self) -> Option<ServerEvent> {
+        match self.events.try_recv() {
+            Ok(ServerEvent::ConnectionClosed(id)) => {
+                if let Some(writer) = self.connections.lock().unwrap().remove(&id) {
+                    writer.shutdown();
+                }
+                Some(ServerEvent::ConnectionClosed(id))
+            }
+            Ok(event) => Some(event),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => None,
+        }
+    }
+
+    fn send_message(&mut self, connection: ConnectionId, message: Vec<u8>) {
+        match self.connections.lock().unwrap().get(&connection) {
+            Some(writer) => writer.send(message),
+            None => println!(
+                "WARNING: request to send message to client {}, but it is not connected!",
+                connection
+            ),
+        }
+    }
+
+    fn disconnect(&mut self, connection: ConnectionId) {
+        // The messages that were already sent are still delivered, then the reader thread
+        // notices that the connection is closed and sends the `ConnectionClosed` event
+        self.connections.lock().unwrap().remove(&connection);
+    }
+}
+
+impl Drop for TcpServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        for (_, writer) in self.connections.lock().unwrap().drain() {
+            writer.shutdown();
+        }
+    }
+}
+
+pub struct TcpClient {
+    writer: Writer,
+    events: Receiver<ClientEvent>,
+}
+
+impl TcpClient {
+    /// Connect to the server at `addr` and receive messages in a background thread
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect_timeout(&resolve(addr)?, TIMEOUT)?;
+        let (reader, writer) = setup_stream(&stream)?;
+
+        let (events_tx, events) = channel();
+        events_tx.send(ClientEvent::Connection).unwrap();
+        thread::spawn(move || {
+            read_frames(reader, |message| {
+                events_tx.send(ClientEvent::Message(message)).is_ok()
+            });
+            let _ = events_tx.send(ClientEvent::ConnectionClosed);
+        });
+
+        Ok(Self { writer, events })
+    }
+}
+
+impl Client for TcpClient {
+    fn next_event(&mut self) -> Option<ClientEvent> {
+        match self.events.try_recv() {
+            Ok(event) => Some(event),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(ClientEvent::ConnectionClosed),
+        }
+    }
+
+    fn send_message(&mut self, message: Vec<u8>) {
+        self.writer.send(message);
+    }
+}
+
+impl Drop for TcpClient {
+    fn drop(&mut self) {
+        self.writer.shutdown();
+    }
+}
+
+fn resolve(addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+    addr.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "address did not resolve to anything",
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Poll `next_event` until it returns an event, or panic after `TIMEOUT`
+    fn wait<T>(mut next_event: impl FnMut() -> Option<T>) -> T {
+        let deadline = Instant::now() + TIMEOUT;
+        while Instant::now() < deadline {
+            if let Some(event) = next_event() {
+                return event;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("timed out waiting for an event");
+    }
+
+    #[test]
+    fn loopback() {
+        let mut server = TcpServer::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpClient::connect(server.local_addr()).unwrap();
+
+        match wait(|| client.next_event()) {
+            ClientEvent::Connection => (),
+            _ => panic!("expected the client to connect"),
+        }
+        let id = match wait(|| server.next_event()) {
+            ServerEvent::Connection(id) => id,
+            _ => panic!("expected a new connection"),
+        };
+
+        client.send_message(b"ping".to_vec());
+        match wait(|| server.next_event()) {
+            ServerEvent::Message(from, message) => {
+                assert_eq!(from, id);
+                assert_eq!(message, b"ping");
+            }
+            _ => panic!("expected a message from the client"),
+        }
+
+        server.send_message(id, b"pong".to_vec());
+        match wait(|| client.next_event()) {
+            ClientEvent::Message(message) => assert_eq!(message, b"pong"),
+            _ => panic!("expected a message from the server"),
+        }
+
+        // Messages sent right before disconnecting are still delivered
+        server.send_message(id, b"bye".to_vec());
+        server.disconnect(id);
+        match wait(|| client.next_event()) {
+            ClientEvent::Message(message) => assert_eq!(message, b"bye"),
+            _ => panic!("expected the last message of the server"),
+        }
+        match wait(|| client.next_event()) {
+            ClientEvent::ConnectionClosed => (),
+            _ => panic!("expected the connection to be closed"),
+        }
+        match wait(|| server.next_event()) {
+            ServerEvent::ConnectionClosed(closed) => assert_eq!(closed, id),
+            _ => panic!("expected the connection to be closed"),
+        }
+    }
+}
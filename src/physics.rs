@@ -0,0 +1,246 @@
+//! Collisions between axis-aligned bounding boxes and solid blocks.
+
+use crate::block::{BlockPos, BlockRegistry};
+use crate::raycast::BlockSource;
+use nalgebra::Vector3;
+
+/// Gap left between a box and the block it collided with
+const EPSILON: f64 = 1e-6;
+/// How far a box stuck in solid blocks can be pushed out of them
+const MAX_PUSH_OUT: f64 = 2.0;
+
+/// Axis-aligned bounding box
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vector3<f64>,
+    pub max: Vector3<f64>,
+}
+
+/// Movement of a box after collisions
+pub struct Movement {
+    /// How far the box could move
+    pub delta: Vector3<f64>,
+    /// Whether the box was stopped along each axis
+    pub collisions: [bool; 3],
+}
+
+impl Aabb {
+    pub fn new(min: Vector3<f64>, max: Vector3<f64>) -> Self {
+        Self { min, max }
+    }
+
+    fn translate(&mut self, axis: usize, delta: f64) {
+        self.min[axis] += delta;
+        self.max[axis] += delta;
+    }
+
+    /// Does the box intersect a block ?
+    pub fn intersects(&self, pos: BlockPos) -> bool {
+        (0..3).all(|i| self.min[i] < (pos.0[i] + 1) as f64 && (pos.0[i] as f64) < self.max[i])
+    }
+
+    /// Range of block coordinates intersecting the box along an axis
+    fn block_range(&self, axis: usize) -> std::ops::Range<i64> {
+        (self.min[axis].floor() as i64)..(self.max[axis].ceil() as i64)
+    }
+}
+
+/// Unloaded blocks are considered solid
+fn is_solid(world: &impl BlockSource, registry: &BlockRegistry, pos: BlockPos) -> bool {
+    match world.get_block(pos) {
+        Some(block) => registry.get_block(block).is_solid(),
+        None => true,
+    }
+}
+
+/// Is there a solid block in a slice of blocks ? The slice is the set of blocks with coordinate
+/// `layer` along `axis` intersecting the box along the other axes.
+fn layer_is_solid(
+    world: &impl BlockSource,
+    registry: &BlockRegistry,
+    aabb: &Aabb,
+    axis: usize,
+    layer: i64,
+) -> bool {
+    let (a1, a2) = ((axis + 1) % 3, (axis + 2) % 3);
+    for i in aabb.block_range(a1) {
+        for j in aabb.block_range(a2) {
+            let mut pos = [0; 3];
+            pos[axis] = layer;
+            pos[a1] = i;
+            pos[a2] = j;
+            if is_solid(world, registry, BlockPos(pos)) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Does the box intersect a solid block ?
+pub fn intersects_solid(world: &impl BlockSource, registry: &BlockRegistry, aabb: &Aabb) -> bool {
+    aabb.block_range(0)
+        .any(|layer| layer_is_solid(world, registry, aabb, 0, layer))
+}
+
+/// Shortest translation along a single axis that moves a box out of the solid blocks it
+/// intersects, if there is one shorter than `MAX_PUSH_OUT`. Upwards is preferred on ties.
+fn push_out(
+    world: &impl BlockSource,
+    registry: &BlockRegistry,
+    aabb: &Aabb,
+) -> Option<(usize, f64)> {
+    let mut candidates = Vec::new();
+    for &axis in &[1, 0, 2] {
+        // Align the min face with the top of a block, or the max face with the bottom of a block
+        let mut top = aabb.min[axis].floor() + 1.0;
+        while top - aabb.min[axis] <= MAX_PUSH_OUT {
+            candidates.push((axis, top - aabb.min[axis] + EPSILON));
+            top += 1.0;
+        }
+        let mut bottom = aabb.max[axis].ceil() - 1.0;
+        while aabb.max[axis] - bottom <= MAX_PUSH_OUT {
+            candidates.push((axis, bottom - aabb.max[axis] - EPSILON));
+            bottom -= 1.0;
+        }
+    }
+    // The sort is stable, so the vertical candidates stay first
+    candidates.sort_by(|a, b| a.1.abs().partial_cmp(&b.1.abs()).unwrap());
+    candidates.into_iter().find(|&(axis, d)| {
+        let mut pushed = *aabb;
+        pushed.translate(axis, d);
+        !intersects_solid(world, registry, &pushed)
+    })
+}
+
+/// Move a box by `delta`, one axis at a time, stopping it before it enters solid blocks.
+/// A box that already intersects solid blocks is first pushed out of them, and the push is
+/// included in the movement. If it can't be pushed out, it moves freely so that players can't
+/// get stuck.
+pub fn move_aabb(
+    world: &impl BlockSource,
+    registry: &BlockRegistry,
+    mut aabb: Aabb,
+    delta: Vector3<f64>,
+) -> Movement {
+    let mut movement = Movement {
+        delta,
+        collisions: [false; 3],
+    };
+    let mut push = Vector3::zeros();
+    if intersects_solid(world, registry, &aabb) {
+        match push_out(world, registry, &aabb) {
+            Some((axis, d)) => {
+                aabb.translate(axis, d);
+                push[axis] = d;
+            }
+            None => return movement,
+        }
+    }
+
+    // Vertical movement first, so that falling players land before moving horizontally
+    for &axis in &[1, 0, 2] {
+        let d = delta[axis];
+        if d > 0.0 {
+            // Blocks entered by the max face of the box
+            for layer in (aabb.max[axis].ceil() as i64)..((aabb.max[axis] + d).ceil() as i64) {
+                if layer_is_solid(world, registry, &aabb, axis, layer) {
+                    movement.delta[axis] = f64::max(0.0, layer as f64 - aabb.max[axis] - EPSILON);
+                    movement.collisions[axis] = true;
+                    break;
+                }
+            }
+        } else if d < 0.0 {
+            // Blocks entered by the min face of the box
+            let mut layer = aabb.min[axis].floor() as i64 - 1;
+            while layer >= (aabb.min[axis] + d).floor() as i64 {
+                if layer_is_solid(world, registry, &aabb, axis, layer) {
+                    movement.delta[axis] =
+                        f64::min(0.0, (layer + 1) as f64 - aabb.min[axis] + EPSILON);
+                    movement.collisions[axis] = true;
+                    break;
+                }
+                layer -= 1;
+            }
+        }
+        aabb.translate(axis, movement.delta[axis]);
+    }
+    movement.delta += push;
+    movement
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{
+        create_headless_block_registry, load_block_definitions, ChunkMap, ChunkPos, ChunkState,
+        ServerChunk,
+    };
+    use crate::server::worldgen::flat::{parse_flat_layers, FlatStage};
+    use crate::server::worldgen::{Pipeline, WorldGenerator};
+    use std::path::Path;
+
+    fn flat_world(registry: &BlockRegistry) -> ChunkMap {
+        let mut generator = Pipeline::new(registry);
+        generator.add_stage(FlatStage::new(
+            parse_flat_layers("8*stone,3*dirt,grass", registry).unwrap(),
+        ));
+        let mut world = ChunkMap::new();
+        for y in -1..=0 {
+            let pos = ChunkPos([0, y, 0]);
+            world.insert(
+                pos,
+                ChunkState::Generated(ServerChunk::new(
+                    generator.generate(pos),
+                    generator.biomes(0, 0),
+                    0,
+                )),
+            );
+        }
+        world
+    }
+
+    #[test]
+    fn fall_on_flat_ground() {
+        let registry = create_headless_block_registry(&load_block_definitions(Path::new(
+            "assets/blocks.toml",
+        )));
+        let world = flat_world(&registry);
+
+        // The top of the grass layer is at height 1
+        let aabb = Aabb::new(Vector3::new(10.2, 5.0, 10.2), Vector3::new(10.8, 6.8, 10.8));
+        let movement = move_aabb(&world, &registry, aabb, Vector3::new(0.5, -10.0, 0.0));
+        assert_eq!(movement.collisions, [false, true, false]);
+        assert!((aabb.min.y + movement.delta.y - 1.0).abs() < 1e-3);
+        assert_eq!(movement.delta.x, 0.5);
+    }
+
+    #[test]
+    fn push_out_of_ground() {
+        let registry = create_headless_block_registry(&load_block_definitions(Path::new(
+            "assets/blocks.toml",
+        )));
+        let world = flat_world(&registry);
+
+        // Half a block deep in the grass layer
+        let aabb = Aabb::new(Vector3::new(10.2, 0.5, 10.2), Vector3::new(10.8, 2.3, 10.8));
+        assert!(intersects_solid(&world, &registry, &aabb));
+        let movement = move_aabb(&world, &registry, aabb, Vector3::new(0.5, -1.0, 0.0));
+        assert!((aabb.min.y + movement.delta.y - 1.0).abs() < 1e-3);
+        assert_eq!(movement.collisions, [false, true, false]);
+        assert!((movement.delta.x - 0.5).abs() < 1e-9);
+        let mut moved = aabb;
+        moved.min += movement.delta;
+        moved.max += movement.delta;
+        assert!(!intersects_solid(&world, &registry, &moved));
+
+        // Too deep to be pushed out: the box moves freely
+        let aabb = Aabb::new(
+            Vector3::new(10.2, -8.0, 10.2),
+            Vector3::new(10.8, -6.2, 10.8),
+        );
+        let movement = move_aabb(&world, &registry, aabb, Vector3::new(0.5, -1.0, 0.0));
+        assert_eq!(movement.delta, Vector3::new(0.5, -1.0, 0.0));
+        assert_eq!(movement.collisions, [false; 3]);
+    }
+}
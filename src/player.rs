@@ -1,11 +1,30 @@
 //! `Player`-related data structures.
 
-use crate::block::{ChunkMap, ChunkPos, ChunkState};
+use crate::block::{BlockPos, BlockRegistry, ChunkMap, ChunkPos, ChunkState};
 use crate::config::Config;
+use crate::physics::{move_aabb, Aabb};
 use nalgebra::Vector3;
 use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+/// Maximum distance between a player and the blocks they can break or place
+pub const REACH_DISTANCE: f64 = 10.0;
+/// Half of the width of the player's hitbox
+const PLAYER_HALF_WIDTH: f64 = 0.3;
+/// Height of the player's hitbox
+const PLAYER_HEIGHT: f64 = 1.8;
+/// Height of the eyes above the bottom of the hitbox
+const PLAYER_EYE_HEIGHT: f64 = 1.6;
+/// In blocks/s²
+const GRAVITY: f64 = 25.0;
+/// In blocks/s
+const JUMP_SPEED: f64 = 8.0;
+/// In blocks/s
+const MAX_FALL_SPEED: f64 = 50.0;
+/// Player names are also file names, so they are short and only contain ASCII letters, digits,
+/// `-` and `_`
+const MAX_PLAYER_NAME_LENGTH: usize = 32;
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct PlayerPos(pub [f64; 3]);
 
@@ -19,43 +38,126 @@ pub struct PlayerInput {
     pub pitch: f64,
 }
 
+/// How a player moves
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MovementMode {
+    /// Gravity and collisions
+    Walk,
+    /// Collisions but no gravity
+    Fly,
+    /// No gravity and no collisions
+    Noclip,
+}
+
+/// What is kept of a player between sessions. New fields must have a default value, so that the
+/// records of older versions can still be read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerRecord {
+    /// Position of the eyes
+    pub pos: [f64; 3],
+    /// Yaw in degrees
+    pub yaw: f64,
+    /// Pitch in degrees
+    pub pitch: f64,
+    pub mode: MovementMode,
+}
+
 /// A server-side player
 pub struct Player {
+    pub name: String,
+    /// Position of the eyes
     pub pos: Vector3<f64>,
     /// Yaw in degrees
     pub yaw: f64,
     /// Pitch in degrees
     pub pitch: f64,
     pub render_distance: u64,
-    pub chunks: HashMap<ChunkPos, ()>,
+    /// Chunks the player has, and their versions, see `ServerChunk::version`
+    pub chunks: HashMap<ChunkPos, u64>,
     pub keys: u8,
+    pub mode: MovementMode,
+    pub velocity: Vector3<f64>,
+    pub on_ground: bool,
 }
 
 impl Player {
-    pub fn tick(&mut self, dt: f64, config: &Config, world: &ChunkMap) {
+    /// A player who just logged in
+    pub fn new(name: String, record: &PlayerRecord) -> Self {
+        Self {
+            name,
+            pos: record.pos.into(),
+            yaw: record.yaw,
+            pitch: record.pitch,
+            render_distance: 0,
+            chunks: HashMap::new(),
+            keys: 0,
+            mode: record.mode,
+            velocity: Vector3::zeros(),
+            on_ground: false,
+        }
+    }
+
+    pub fn record(&self) -> PlayerRecord {
+        PlayerRecord {
+            pos: self.pos.into(),
+            yaw: self.yaw,
+            pitch: self.pitch,
+            mode: self.mode,
+        }
+    }
+
+    pub fn tick(&mut self, dt: f64, config: &Config, world: &ChunkMap, registry: &BlockRegistry) {
         let mut speedup = 1.0;
         if self.keys & (1 << 6) > 0 {
             speedup = config.ctrl_speedup;
         }
+        let speed = speedup * config.player_speed * dt;
 
-        let old_pos = self.pos.clone();
+        let mut delta = Vector3::zeros();
         if self.keys & (1 << 0) > 0 {
-            self.pos += speedup * self.mv_direction(0.0) * (config.player_speed * dt);
+            delta += self.mv_direction(0.0) * speed;
         }
         if self.keys & (1 << 1) > 0 {
-            self.pos += speedup * self.mv_direction(90.0) * (config.player_speed * dt);
+            delta += self.mv_direction(90.0) * speed;
         }
         if self.keys & (1 << 2) > 0 {
-            self.pos += speedup * self.mv_direction(180.0) * (config.player_speed * dt);
+            delta += self.mv_direction(180.0) * speed;
         }
         if self.keys & (1 << 3) > 0 {
-            self.pos += speedup * self.mv_direction(270.0) * (config.player_speed * dt);
+            delta += self.mv_direction(270.0) * speed;
         }
-        if self.keys & (1 << 4) > 0 {
-            self.pos.y += speedup * config.player_speed * dt;
+        match self.mode {
+            MovementMode::Walk => {
+                // Jump
+                if self.keys & (1 << 4) > 0 && self.on_ground {
+                    self.velocity.y = JUMP_SPEED;
+                }
+                self.velocity.y = f64::max(self.velocity.y - GRAVITY * dt, -MAX_FALL_SPEED);
+                delta.y += self.velocity.y * dt;
+            }
+            MovementMode::Fly | MovementMode::Noclip => {
+                self.velocity.y = 0.0;
+                if self.keys & (1 << 4) > 0 {
+                    delta.y += speed;
+                }
+                if self.keys & (1 << 5) > 0 {
+                    delta.y -= speed;
+                }
+            }
         }
-        if self.keys & (1 << 5) > 0 {
-            self.pos.y -= speedup * config.player_speed * dt;
+
+        let old_pos = self.pos.clone();
+        if self.mode == MovementMode::Noclip {
+            self.pos += delta;
+            self.on_ground = false;
+        } else {
+            let movement = move_aabb(world, registry, self.aabb(), delta);
+            self.pos += movement.delta;
+            self.on_ground = movement.collisions[1] && delta.y < 0.0;
+            if movement.collisions[1] {
+                self.velocity.y = 0.0;
+            }
         }
 
         let chunk_pos = self.get_pos().chunk_pos();
@@ -76,6 +178,16 @@ impl Player {
         PlayerPos(self.pos.into())
     }
 
+    /// The player's hitbox
+    pub fn aabb(&self) -> Aabb {
+        let offset = Vector3::new(PLAYER_HALF_WIDTH, 0.0, PLAYER_HALF_WIDTH);
+        let feet = self.pos - Vector3::new(0.0, PLAYER_EYE_HEIGHT, 0.0);
+        Aabb::new(
+            feet - offset,
+            feet + offset + Vector3::new(0.0, PLAYER_HEIGHT, 0.0),
+        )
+    }
+
     pub fn set_input(&mut self, input: &PlayerInput) {
         self.keys = input.keys;
         self.yaw = input.yaw;
@@ -83,6 +195,25 @@ impl Player {
     }
 }
 
+pub fn is_valid_player_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_PLAYER_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl MovementMode {
+    /// The mode that comes after this one when the player switches modes
+    pub fn next(self) -> Self {
+        match self {
+            MovementMode::Walk => MovementMode::Fly,
+            MovementMode::Fly => MovementMode::Noclip,
+            MovementMode::Noclip => MovementMode::Walk,
+        }
+    }
+}
+
 impl PlayerPos {
     pub fn chunk_pos(self) -> ChunkPos {
         use crate::CHUNK_SIZE;
@@ -97,4 +228,13 @@ impl PlayerPos {
         }
         ChunkPos(ret)
     }
+
+    /// Position of the block containing the player
+    pub fn block_pos(self) -> BlockPos {
+        let mut ret = [0; 3];
+        for i in 0..3 {
+            ret[i] = self.0[i].floor() as i64;
+        }
+        BlockPos(ret)
+    }
 }
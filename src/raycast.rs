@@ -0,0 +1,240 @@
+//! Voxel raycasting, using the traversal algorithm of Amanatides and Woo.
+
+use crate::block::{BlockId, BlockPos, BlockRegistry, ChunkMap, ChunkState};
+use nalgebra::Vector3;
+
+/// Something that contains blocks, such as the server's `ChunkMap`
+pub trait BlockSource {
+    /// Get a block, or `None` if it is not loaded
+    fn get_block(&self, pos: BlockPos) -> Option<BlockId>;
+}
+
+impl BlockSource for ChunkMap {
+    fn get_block(&self, pos: BlockPos) -> Option<BlockId> {
+        match self.get(&pos.chunk_pos()) {
+            Some(ChunkState::Generated(chunk)) => {
+                let [x, y, z] = pos.pos_in_chunk();
+                Some(chunk.blocks.get(x, y, z))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The first solid block hit by a ray
+#[derive(Debug, Clone, Copy)]
+pub struct RaycastHit {
+    pub pos: BlockPos,
+    pub block: BlockId,
+    /// The face the ray entered the block through, using the same order as the faces of
+    /// `Block::render`. `None` if the ray started inside the block.
+    pub face: Option<usize>,
+    /// Distance between the origin of the ray and the hit point
+    pub distance: f64,
+}
+
+/// Offset from a block to the block adjacent to each of its faces
+const FACE_OFFSETS: [[i64; 3]; 6] = [
+    [0, 0, -1],
+    [0, 0, 1],
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+];
+
+/// The face a ray enters a block through, indexed by axis and by whether the ray goes towards
+/// positive coordinates along this axis
+const ENTRY_FACES: [[usize; 2]; 3] = [[2, 3], [4, 5], [1, 0]];
+
+impl RaycastHit {
+    /// Position of the block in front of the hit face, where a new block would be placed
+    pub fn adjacent_pos(&self) -> Option<BlockPos> {
+        self.face.map(|face| {
+            let mut pos = self.pos;
+            for i in 0..3 {
+                pos.0[i] += FACE_OFFSETS[face][i];
+            }
+            pos
+        })
+    }
+}
+
+/// Find the first solid block along a ray, if it is at most `max_distance` away from `origin`.
+/// The ray stops at unloaded blocks.
+pub fn raycast(
+    world: &impl BlockSource,
+    registry: &BlockRegistry,
+    origin: Vector3<f64>,
+    dir: Vector3<f64>,
+    max_distance: f64,
+) -> Option<RaycastHit> {
+    let dir = dir.normalize();
+    let mut pos = [0; 3];
+    // Direction of the steps along each axis
+    let mut step = [0; 3];
+    // Distance along the ray to the next block boundary along each axis
+    let mut t_max = [std::f64::INFINITY; 3];
+    // Distance along the ray between two block boundaries along each axis
+    let mut t_delta = [std::f64::INFINITY; 3];
+    for i in 0..3 {
+        pos[i] = origin[i].floor() as i64;
+        if dir[i] > 0.0 {
+            step[i] = 1;
+            t_max[i] = (pos[i] as f64 + 1.0 - origin[i]) / dir[i];
+            t_delta[i] = 1.0 / dir[i];
+        } else if dir[i] < 0.0 {
+            step[i] = -1;
+            t_max[i] = (pos[i] as f64 - origin[i]) / dir[i];
+            t_delta[i] = -1.0 / dir[i];
+        }
+    }
+
+    let mut face = None;
+    let mut distance = 0.0;
+    loop {
+        let block = world.get_block(BlockPos(pos))?;
+        if registry.get_block(block).is_solid() {
+            return Some(RaycastHit {
+                pos: BlockPos(pos),
+                block,
+                face,
+                distance,
+            });
+        }
+
+        // Move to the closest block boundary
+        let mut axis = 0;
+        for i in 1..3 {
+            if t_max[i] < t_max[axis] {
+                axis = i;
+            }
+        }
+        distance = t_max[axis];
+        if distance > max_distance {
+            return None;
+        }
+        pos[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+        face = Some(ENTRY_FACES[axis][if step[axis] > 0 { 1 } else { 0 }]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{
+        create_headless_block_registry, load_block_definitions, ChunkPos, ServerChunk,
+    };
+    use crate::server::worldgen::flat::{parse_flat_layers, FlatStage};
+    use crate::server::worldgen::{Pipeline, WorldGenerator};
+    use std::path::Path;
+
+    /// Flat ground whose top is at height 1, two blocks of stone above it, and the chunks
+    /// `(0, -1, 0)` and `(0, 0, 0)` loaded
+    fn world(registry: &BlockRegistry) -> ChunkMap {
+        let mut generator = Pipeline::new(registry);
+        generator.add_stage(FlatStage::new(
+            parse_flat_layers("stone,3*dirt,grass", registry).unwrap(),
+        ));
+        let mut world = ChunkMap::new();
+        for y in -1..=0 {
+            let pos = ChunkPos([0, y, 0]);
+            let mut chunk = ServerChunk::new(generator.generate(pos), generator.biomes(0, 0), 0);
+            if y == 0 {
+                let stone = registry.get_id("stone").unwrap();
+                chunk.blocks.set(10, 3, 5, stone);
+                chunk.blocks.set(5, 8, 5, stone);
+            }
+            world.insert(pos, ChunkState::Generated(chunk));
+        }
+        world
+    }
+
+    fn cast(origin: [f64; 3], dir: [f64; 3], max_distance: f64) -> Option<RaycastHit> {
+        let registry = create_headless_block_registry(&load_block_definitions(Path::new(
+            "assets/blocks.toml",
+        )));
+        let world = world(&registry);
+        raycast(
+            &world,
+            &registry,
+            Vector3::new(origin[0], origin[1], origin[2]),
+            Vector3::new(dir[0], dir[1], dir[2]),
+            max_distance,
+        )
+    }
+
+    fn assert_hit(hit: Option<RaycastHit>, pos: [i64; 3], face: Option<usize>, distance: f64) {
+        let hit = hit.expect("the ray didn't hit anything");
+        assert_eq!(hit.pos.0, pos);
+        assert_eq!(hit.face, face);
+        assert!((hit.distance - distance).abs() < 1e-9);
+    }
+
+    #[test]
+    fn axes() {
+        let hit = cast([5.5, 3.5, 5.5], [1.0, 0.0, 0.0], 10.0);
+        assert_hit(hit, [10, 3, 5], Some(3), 4.5);
+        assert_eq!(hit.unwrap().adjacent_pos().unwrap().0, [9, 3, 5]);
+        assert_hit(
+            cast([5.5, 3.5, 5.5], [0.0, 1.0, 0.0], 10.0),
+            [5, 8, 5],
+            Some(5),
+            4.5,
+        );
+        assert_hit(
+            cast([10.5, 3.5, 0.5], [0.0, 0.0, 1.0], 10.0),
+            [10, 3, 5],
+            Some(0),
+            4.5,
+        );
+    }
+
+    #[test]
+    fn negative_directions() {
+        assert_hit(
+            cast([15.5, 3.5, 5.5], [-1.0, 0.0, 0.0], 10.0),
+            [10, 3, 5],
+            Some(2),
+            4.5,
+        );
+        assert_hit(
+            cast([5.5, 3.5, 20.5], [0.0, -1.0, 0.0], 10.0),
+            [5, 0, 20],
+            Some(4),
+            2.5,
+        );
+        assert_hit(
+            cast([10.5, 3.5, 9.5], [0.0, 0.0, -1.0], 10.0),
+            [10, 3, 5],
+            Some(1),
+            3.5,
+        );
+    }
+
+    #[test]
+    fn diagonal() {
+        // The ray crosses the top of the ground at x = 4.8, z = 5.1
+        let hit = cast([0.3, 5.5, 0.6], [1.0, -1.0, 1.0], 10.0);
+        assert_hit(hit, [4, 0, 5], Some(4), 4.5 * 3f64.sqrt());
+    }
+
+    #[test]
+    fn inside_solid_block() {
+        assert_hit(
+            cast([5.5, 0.5, 5.5], [1.0, 1.0, 0.0], 10.0),
+            [5, 0, 5],
+            None,
+            0.0,
+        );
+    }
+
+    #[test]
+    fn misses() {
+        // Too far
+        assert!(cast([5.5, 3.5, 20.5], [0.0, -1.0, 0.0], 2.0).is_none());
+        // The chunk (1, 0, 0) is not loaded
+        assert!(cast([20.5, 3.5, 5.5], [1.0, 0.0, 0.0], 100.0).is_none());
+    }
+}
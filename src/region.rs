@@ -0,0 +1,316 @@
+//! Region files, used to store chunks on disk.
+//! A region is a cube of `REGION_SIZE`³ chunks stored in a single file. The file starts with an
+//! offset table holding the position and the length of every chunk in the file, followed by the
+//! chunk data itself, which is run-length encoded using `network::serialize_blocks`.
+//! Region files are never modified in place: saving rewrites the whole file with
+//! `util::write_atomic`, so chunks should be saved in batches.
+
+use crate::block::{BlockId, BlockIdMap, ChunkPos, PalettedChunk};
+use crate::network::{deserialize_blocks, serialize_blocks};
+use crate::util::write_atomic;
+use crate::CHUNK_SIZE;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Side of a region, in chunks
+pub const REGION_SIZE: i64 = 16;
+/// Number of chunks in a region
+const REGION_CHUNKS: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+/// Every entry of the offset table is a `u32` offset followed by a `u32` length
+const HEADER_SIZE: u64 = REGION_CHUNKS as u64 * 8;
+/// Directory of the region files converted by `RegionStorage::remap_blocks`
+const REMAP_DIRECTORY: &str = "remap";
+
+/// Position of a region
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
+pub struct RegionPos(pub [i64; 3]);
+
+/// Reads and writes chunks in the region files of a directory.
+pub struct RegionStorage {
+    path: PathBuf,
+}
+
+impl RegionStorage {
+    /// Open the region directory at `path`, creating it if necessary
+    pub fn new(path: &Path) -> io::Result<Self> {
+        fs::create_dir_all(path)?;
+        Ok(Self {
+            path: path.to_owned(),
+        })
+    }
+
+    /// Load a chunk from its region file. Returns `None` if the chunk was never saved.
+    pub fn load_chunk(&self, pos: ChunkPos) -> io::Result<Option<PalettedChunk>> {
+        let (region, index) = split_pos(pos);
+        let mut file = match File::open(self.region_path(region)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let (offset, length) = read_header_entry(&mut file, index)?;
+        if offset == 0 {
+            return Ok(None);
+        }
+        let mut bytes = vec![0; length as usize];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(&mut bytes)?;
+
+        let blocks = deserialize_blocks(&bytes).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("chunk {:?} is truncated", pos),
+            )
+        })?;
+        if blocks.len() != CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("chunk {:?} has {} blocks", pos, blocks.len()),
+            ));
+        }
+        let mut chunk = Box::new([[[BlockId::from(0); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]);
+        let mut blocks = blocks.into_iter();
+        for chunkyz in chunk.iter_mut() {
+            for chunkz in chunkyz.iter_mut() {
+                for block in chunkz.iter_mut() {
+                    *block = blocks.next().unwrap();
+                }
+            }
+        }
+        Ok(Some(PalettedChunk::from_array(&chunk)))
+    }
+
+    /// Save chunks to their region files, overwriting their previous versions. Every region file
+    /// holding some of the chunks is rewritten once.
+    pub fn save_chunks<'a>(
+        &self,
+        chunks: impl IntoIterator<Item = (ChunkPos, &'a PalettedChunk)>,
+    ) -> io::Result<()> {
+        let mut regions: HashMap<RegionPos, Vec<(usize, Vec<u8>)>> = HashMap::new();
+        for (pos, chunk) in chunks {
+            let (region, index) = split_pos(pos);
+            let blocks: Vec<BlockId> = chunk
+                .to_array()
+                .iter()
+                .flat_map(|chunkyz| chunkyz.iter())
+                .flat_map(|chunkz| chunkz.iter())
+                .cloned()
+                .collect();
+            regions
+                .entry(region)
+                .or_insert_with(Vec::new)
+                .push((index, serialize_blocks(&blocks)));
+        }
+
+        for (region, new_chunks) in regions {
+            let path = self.region_path(region);
+            let mut chunks = match fs::read(&path) {
+                Ok(bytes) => read_region(&bytes)?,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => vec![None; REGION_CHUNKS],
+                Err(e) => return Err(e),
+            };
+            for (index, bytes) in new_chunks {
+                chunks[index] = Some(bytes);
+            }
+            write_atomic(&path, &encode_region(&chunks))?;
+        }
+        Ok(())
+    }
+
+    /// Convert the blocks of every saved chunk from remote to local ids. The converted region
+    /// files are written to a `remap` directory and don't replace the region files until
+    /// `finish_remap` is called, so the conversion can be interrupted and started again: the
+    /// region files that were already converted are skipped.
+    pub fn remap_blocks(&self, id_map: &BlockIdMap) -> io::Result<()> {
+        let remap_path = self.path.join(REMAP_DIRECTORY);
+        fs::create_dir_all(&remap_path)?;
+        for path in region_files(&self.path)? {
+            let converted_path = remap_path.join(path.file_name().unwrap());
+            if converted_path.exists() {
+                continue;
+            }
+            let mut chunks = read_region(&fs::read(&path)?)?;
+            for bytes in chunks.iter_mut().filter_map(Option::as_mut) {
+                let blocks: Vec<BlockId> = deserialize_blocks(bytes)
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("{} has a truncated chunk", path.display()),
+                        )
+                    })?
+                    .into_iter()
+                    .map(|block| id_map.to_local(block))
+                    .collect();
+                *bytes = serialize_blocks(&blocks);
+            }
+            write_atomic(&converted_path, &encode_region(&chunks))?;
+        }
+        Ok(())
+    }
+
+    /// Replace the region files by the ones `remap_blocks` converted. It can be interrupted and
+    /// called again.
+    pub fn finish_remap(&self) -> io::Result<()> {
+        let remap_path = self.path.join(REMAP_DIRECTORY);
+        if !remap_path.exists() {
+            return Ok(());
+        }
+        for path in region_files(&remap_path)? {
+            fs::rename(&path, self.path.join(path.file_name().unwrap()))?;
+        }
+        fs::remove_dir_all(&remap_path)
+    }
+
+    fn region_path(&self, region: RegionPos) -> PathBuf {
+        self.path.join(format!(
+            "r.{}.{}.{}.region",
+            region.0[0], region.0[1], region.0[2]
+        ))
+    }
+}
+
+/// Paths of the region files of a directory
+fn region_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.extension() == Some(OsStr::new("region")) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Get the region containing a chunk, and the index of the chunk in that region
+fn split_pos(pos: ChunkPos) -> (RegionPos, usize) {
+    let mut region = [0; 3];
+    let mut index = 0;
+    for i in 0..3 {
+        region[i] = pos.0[i].div_euclid(REGION_SIZE);
+        let local = pos.0[i].rem_euclid(REGION_SIZE);
+        index = index * REGION_SIZE as usize + local as usize;
+    }
+    (RegionPos(region), index)
+}
+
+fn read_header_entry(file: &mut File, index: usize) -> io::Result<(u32, u32)> {
+    let mut entry = [0; 8];
+    file.seek(SeekFrom::Start(index as u64 * 8))?;
+    file.read_exact(&mut entry)?;
+    let offset = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+    let length = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+    Ok((offset, length))
+}
+
+/// Split the contents of a region file into the encoded data of its chunks, indexed like the
+/// offset table
+fn read_region(bytes: &[u8]) -> io::Result<Vec<Option<Vec<u8>>>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "truncated region file");
+    if (bytes.len() as u64) < HEADER_SIZE {
+        return Err(invalid());
+    }
+    let mut chunks = vec![None; REGION_CHUNKS];
+    for (index, chunk) in chunks.iter_mut().enumerate() {
+        let entry = &bytes[index * 8..(index + 1) * 8];
+        let offset = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+        let length = u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]) as usize;
+        if offset == 0 {
+            continue;
+        }
+        let data = bytes.get(offset..offset + length).ok_or_else(invalid)?;
+        *chunk = Some(data.to_vec());
+    }
+    Ok(chunks)
+}
+
+/// Contents of a region file holding the given chunks, indexed like the offset table
+fn encode_region(chunks: &[Option<Vec<u8>>]) -> Vec<u8> {
+    let mut bytes = vec![0; HEADER_SIZE as usize];
+    for (index, chunk) in chunks.iter().enumerate() {
+        if let Some(chunk) = chunk {
+            let entry = encode_header_entry(bytes.len() as u32, chunk.len() as u32);
+            bytes[index * 8..(index + 1) * 8].copy_from_slice(&entry);
+            bytes.extend_from_slice(chunk);
+        }
+    }
+    bytes
+}
+
+fn encode_header_entry(offset: u32, length: u32) -> [u8; 8] {
+    let mut entry = [0; 8];
+    entry[..4].copy_from_slice(&offset.to_le_bytes());
+    entry[4..].copy_from_slice(&length.to_le_bytes());
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockTable;
+
+    fn uniform_chunk(block: u16) -> PalettedChunk {
+        PalettedChunk::from_array(&Box::new(
+            [[[BlockId::from(block); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+        ))
+    }
+
+    #[test]
+    fn save_and_load_chunks() {
+        let path = std::env::temp_dir().join(format!("voxel-rs-region-{}", std::process::id()));
+        let storage = RegionStorage::new(&path).unwrap();
+        let (first, second) = (ChunkPos([0, 0, 0]), ChunkPos([-1, 3, 20]));
+        storage
+            .save_chunks(vec![
+                (first, &uniform_chunk(1)),
+                (second, &uniform_chunk(2)),
+            ])
+            .unwrap();
+        // Overwrite a chunk of a region that already exists
+        storage
+            .save_chunks(vec![(first, &uniform_chunk(3))])
+            .unwrap();
+
+        let block = |pos| storage.load_chunk(pos).unwrap().map(|c| c.get(1, 2, 3));
+        assert_eq!(block(first), Some(BlockId::from(3)));
+        assert_eq!(block(second), Some(BlockId::from(2)));
+        assert_eq!(block(ChunkPos([1, 0, 0])), None);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn interrupted_remap() {
+        let path = std::env::temp_dir().join(format!("voxel-rs-remap-{}", std::process::id()));
+        let storage = RegionStorage::new(&path).unwrap();
+        let (first, second) = (ChunkPos([0, 0, 0]), ChunkPos([-20, 0, 0]));
+        storage
+            .save_chunks(vec![
+                (first, &uniform_chunk(1)),
+                (second, &uniform_chunk(2)),
+            ])
+            .unwrap();
+        let table = |ids: &[(&str, u16)]| -> BlockTable {
+            ids.iter()
+                .map(|&(name, id)| (name.to_owned(), BlockId::from(id)))
+                .collect()
+        };
+        let id_map = BlockIdMap::from_tables(
+            &table(&[("air", 0), ("stone", 1), ("dirt", 2)]),
+            &table(&[("air", 0), ("dirt", 1), ("stone", 2)]),
+        );
+
+        // The region files are only replaced once every region is converted
+        storage.remap_blocks(&id_map).unwrap();
+        let block = |pos| storage.load_chunk(pos).unwrap().map(|c| c.get(1, 2, 3));
+        assert_eq!(block(first), Some(BlockId::from(1)));
+        // Resume a conversion that stopped before the first region
+        fs::remove_file(path.join(REMAP_DIRECTORY).join("r.0.0.0.region")).unwrap();
+        storage.remap_blocks(&id_map).unwrap();
+        storage.finish_remap().unwrap();
+        assert_eq!(block(first), Some(BlockId::from(2)));
+        assert_eq!(block(second), Some(BlockId::from(1)));
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
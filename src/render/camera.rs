@@ -12,6 +12,7 @@ pub const MOVE_RIGHT: u32 = 32;
 pub const MOVE_UP: u32 = 57;
 pub const MOVE_DOWN: u32 = 42;
 pub const CONTROL: u32 = 29;
+pub const SWITCH_MOVEMENT_MODE: u32 = 33;
 
 pub struct Camera {
     position: Vector3<f64>,
@@ -28,7 +29,7 @@ pub struct Camera {
 impl Camera {
     pub fn new(win_w: u32, win_h: u32, config: &Config) -> Camera {
         Camera {
-            position: Vector3::from([config.player_x, config.player_y, config.player_z]),
+            position: Vector3::zeros(),
             yaw: 0.0,
             pitch: 0.0,
             win_w,
@@ -89,4 +90,9 @@ impl Camera {
     pub fn get_yaw_pitch(&self) -> [f64; 2] {
         [self.yaw, self.pitch]
     }
+
+    pub fn set_yaw_pitch(&mut self, yaw: f64, pitch: f64) {
+        self.yaw = yaw;
+        self.pitch = f64::max(-90.0, f64::min(90.0, pitch));
+    }
 }
@@ -0,0 +1,369 @@
+//! Schematics: boxes of blocks saved to a file, to be pasted into worlds or placed as structures
+//! by worldgen.
+//! A schematic has an origin, the point that is placed at the paste position and around which it
+//! is rotated. Its file stores the name and id of the blocks it uses, so that it can be pasted in
+//! worlds whose block ids are different, and the blocks run-length encoded using
+//! `network::serialize_blocks`.
+
+use crate::block::{
+    BlockId, BlockIdMap, BlockPos, BlockRegion, BlockRegistry, BlockTable, ChunkArray, ChunkPos,
+};
+use crate::network::{deserialize_blocks, serialize_blocks};
+use crate::util::write_atomic;
+use crate::CHUNK_SIZE;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Version of the schematic files. Files with a newer version can't be loaded.
+pub const SCHEMATIC_FORMAT_VERSION: u32 = 1;
+
+/// Layout of a schematic file
+#[derive(Serialize, Deserialize)]
+struct SchematicFile {
+    format_version: u32,
+    size: [u32; 3],
+    origin: [i64; 3],
+    /// Id of every block of the schematic
+    blocks: BTreeMap<String, BlockId>,
+    /// Blocks encoded by `serialize_blocks`, in the order of a `BlockRegion`
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schematic {
+    /// Position of the origin relative to the lowest corner of the blocks. It may be outside of
+    /// the blocks.
+    pub origin: [i64; 3],
+    pub blocks: BlockRegion,
+}
+
+/// How a schematic is pasted
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PasteOptions {
+    /// Number of quarter turns around the vertical axis. A quarter turn takes +x to +z.
+    pub rotation: u8,
+    /// Mirror the x axis, before rotating
+    pub mirror: bool,
+    /// Keep the blocks where the schematic has air
+    pub skip_air: bool,
+}
+
+impl PasteOptions {
+    /// Where a position relative to the origin of the schematic ends up
+    fn apply(&self, [x, y, z]: [i64; 3]) -> [i64; 3] {
+        let (mut x, mut z) = (if self.mirror { -x } else { x }, z);
+        for _ in 0..self.rotation % 4 {
+            let t = x;
+            x = -z;
+            z = t;
+        }
+        [x, y, z]
+    }
+
+    /// Inverse of `apply`
+    fn unapply(&self, [x, y, z]: [i64; 3]) -> [i64; 3] {
+        let (mut x, mut z) = (x, z);
+        for _ in 0..self.rotation % 4 {
+            let t = z;
+            z = -x;
+            x = t;
+        }
+        [if self.mirror { -x } else { x }, y, z]
+    }
+}
+
+impl Schematic {
+    /// Schematic of the blocks of a region whose lowest corner is at `min`, such as the ones
+    /// returned by `ServerHandle::capture_region`
+    pub fn from_region(blocks: BlockRegion, min: BlockPos, origin: BlockPos) -> Self {
+        let mut relative_origin = [0; 3];
+        for i in 0..3 {
+            relative_origin[i] = origin.0[i] - min.0[i];
+        }
+        Self {
+            origin: relative_origin,
+            blocks,
+        }
+    }
+
+    /// Lowest corner and highest corner, excluded, of the blocks when the origin is at `pos`
+    pub fn bounds(&self, pos: BlockPos, options: PasteOptions) -> ([i64; 3], [i64; 3]) {
+        let size = self.blocks.size();
+        if size.contains(&0) {
+            return (pos.0, pos.0);
+        }
+        let mut low = [0; 3];
+        let mut high = [0; 3];
+        for i in 0..3 {
+            low[i] = -self.origin[i];
+            high[i] = size[i] as i64 - 1 - self.origin[i];
+        }
+        let (low, high) = (options.apply(low), options.apply(high));
+        let mut min = [0; 3];
+        let mut max = [0; 3];
+        for i in 0..3 {
+            min[i] = pos.0[i] + i64::min(low[i], high[i]);
+            max[i] = pos.0[i] + i64::max(low[i], high[i]) + 1;
+        }
+        (min, max)
+    }
+
+    /// Lowest corner of the rotated and mirrored blocks when the origin is at `pos`, and the
+    /// blocks, air included. They are pasted into worlds by `ServerHandle::paste_schematic`.
+    pub fn to_region(&self, pos: BlockPos, options: PasteOptions) -> (BlockPos, BlockRegion) {
+        let (min, max) = self.bounds(pos, options);
+        let mut size = [0; 3];
+        for i in 0..3 {
+            size[i] = (max[i] - min[i]) as usize;
+        }
+        let mut region = BlockRegion::new(size, BlockId::from(0));
+        for x in 0..size[0] {
+            for y in 0..size[1] {
+                for z in 0..size[2] {
+                    let d = options.unapply([
+                        min[0] + x as i64 - pos.0[0],
+                        min[1] + y as i64 - pos.0[1],
+                        min[2] + z as i64 - pos.0[2],
+                    ]);
+                    let block = self.blocks.get([
+                        (d[0] + self.origin[0]) as usize,
+                        (d[1] + self.origin[1]) as usize,
+                        (d[2] + self.origin[2]) as usize,
+                    ]);
+                    region.set([x, y, z], block);
+                }
+            }
+        }
+        (BlockPos(min), region)
+    }
+
+    /// Call `f` with the position in the chunk and the block of every block of the schematic that
+    /// is pasted in the chunk at `chunk_pos` when the origin is at `pos`
+    fn for_each_in_chunk(
+        &self,
+        pos: BlockPos,
+        options: PasteOptions,
+        chunk_pos: ChunkPos,
+        mut f: impl FnMut([usize; 3], BlockId),
+    ) {
+        let air = BlockId::from(0);
+        let (min, max) = self.bounds(pos, options);
+        let mut chunk_min = [0; 3];
+        let mut low = [0; 3];
+        let mut high = [0; 3];
+        for i in 0..3 {
+            chunk_min[i] = chunk_pos.0[i] * CHUNK_SIZE as i64;
+            low[i] = i64::max(min[i], chunk_min[i]);
+            high[i] = i64::min(max[i], chunk_min[i] + CHUNK_SIZE as i64);
+        }
+        for x in low[0]..high[0] {
+            for y in low[1]..high[1] {
+                for z in low[2]..high[2] {
+                    let d = options.unapply([x - pos.0[0], y - pos.0[1], z - pos.0[2]]);
+                    let block = self.blocks.get([
+                        (d[0] + self.origin[0]) as usize,
+                        (d[1] + self.origin[1]) as usize,
+                        (d[2] + self.origin[2]) as usize,
+                    ]);
+                    if !options.skip_air || block != air {
+                        f(
+                            [
+                                (x - chunk_min[0]) as usize,
+                                (y - chunk_min[1]) as usize,
+                                (z - chunk_min[2]) as usize,
+                            ],
+                            block,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Paste the blocks of the schematic that are inside the chunk at `chunk_pos`, with the
+    /// origin at `pos`
+    pub fn paste_into_array(
+        &self,
+        chunk: &mut ChunkArray,
+        chunk_pos: ChunkPos,
+        pos: BlockPos,
+        options: PasteOptions,
+    ) {
+        self.for_each_in_chunk(pos, options, chunk_pos, |[x, y, z], block| {
+            chunk[x][y][z] = block;
+        });
+    }
+
+    /// Read a schematic. Blocks that no longer exist are replaced by air.
+    pub fn load(path: &Path, block_registry: &BlockRegistry) -> io::Result<Self> {
+        let file: SchematicFile = bincode::deserialize(&fs::read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if file.format_version > SCHEMATIC_FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the schematic has format version {}, newer than {}",
+                    file.format_version, SCHEMATIC_FORMAT_VERSION
+                ),
+            ));
+        }
+        let table: BlockTable = file.blocks.into_iter().collect();
+        let id_map = BlockIdMap::new(&table, block_registry);
+        for name in &id_map.missing {
+            println!(
+                "WARNING: block {} of {} no longer exists, replacing it by air",
+                name,
+                path.display()
+            );
+        }
+        let blocks = deserialize_blocks(&file.data)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "truncated blocks"))?
+            .into_iter()
+            .map(|id| id_map.to_local(id))
+            .collect();
+        let size = [
+            file.size[0] as usize,
+            file.size[1] as usize,
+            file.size[2] as usize,
+        ];
+        let blocks = BlockRegion::from_blocks(size, blocks)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "wrong number of blocks"))?;
+        Ok(Self {
+            origin: file.origin,
+            blocks,
+        })
+    }
+
+    pub fn save(&self, path: &Path, block_registry: &BlockRegistry) -> io::Result<()> {
+        let used: HashSet<BlockId> = self.blocks.blocks().iter().cloned().collect();
+        let names: HashMap<BlockId, String> = block_registry
+            .block_table()
+            .into_iter()
+            .map(|(name, id)| (id, name))
+            .collect();
+        let blocks = used
+            .into_iter()
+            .filter_map(|id| names.get(&id).map(|name| (name.clone(), id)))
+            .collect();
+        let [sx, sy, sz] = self.blocks.size();
+        let file = SchematicFile {
+            format_version: SCHEMATIC_FORMAT_VERSION,
+            size: [sx as u32, sy as u32, sz as u32],
+            origin: self.origin,
+            blocks,
+            data: serialize_blocks(self.blocks.blocks()),
+        };
+        write_atomic(path, &bincode::serialize(&file).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{create_headless_block_registry, load_block_definitions, PalettedChunk};
+
+    /// An L of three blocks with different ids, so that transformations can be told apart
+    fn l_shape(registry: &BlockRegistry) -> Schematic {
+        let mut blocks = BlockRegion::new([2, 1, 2], BlockId::from(0));
+        blocks.set([0, 0, 0], registry.get_id("stone").unwrap());
+        blocks.set([1, 0, 0], registry.get_id("wood").unwrap());
+        blocks.set([0, 0, 1], registry.get_id("sand").unwrap());
+        Schematic {
+            origin: [0, 0, 0],
+            blocks,
+        }
+    }
+
+    /// Block of a region whose lowest corner is at `min`
+    fn block_at(region: &BlockRegion, min: BlockPos, pos: BlockPos) -> BlockId {
+        let mut region_pos = [0; 3];
+        for i in 0..3 {
+            region_pos[i] = (pos.0[i] - min.0[i]) as usize;
+        }
+        region.get(region_pos)
+    }
+
+    fn test_registry() -> BlockRegistry {
+        let definitions = load_block_definitions(Path::new("assets/blocks.toml"));
+        create_headless_block_registry(&definitions)
+    }
+
+    #[test]
+    fn paste_with_rotation_and_mirroring() {
+        let registry = test_registry();
+        let schematic = l_shape(&registry);
+        let id = |name| registry.get_id(name).unwrap();
+        // Where the wood and the sand go, relative to the stone at the origin
+        let cases = [
+            (0, false, [1, 0, 0], [0, 0, 1]),
+            (1, false, [0, 0, 1], [-1, 0, 0]),
+            (2, false, [-1, 0, 0], [0, 0, -1]),
+            (3, false, [0, 0, -1], [1, 0, 0]),
+            (0, true, [-1, 0, 0], [0, 0, 1]),
+            (1, true, [0, 0, -1], [-1, 0, 0]),
+        ];
+        for &(rotation, mirror, wood, sand) in &cases {
+            let options = PasteOptions {
+                rotation,
+                mirror,
+                skip_air: false,
+            };
+            let origin = BlockPos([0, 1, 0]);
+            let (min, region) = schematic.to_region(origin, options);
+            let offset = |d: [i64; 3]| BlockPos([d[0], d[1] + 1, d[2]]);
+            assert_eq!(block_at(&region, min, origin), id("stone"));
+            assert_eq!(block_at(&region, min, offset(wood)), id("wood"));
+            assert_eq!(block_at(&region, min, offset(sand)), id("sand"));
+
+            // Capturing the pasted blocks gives the transformed schematic
+            let captured = Schematic::from_region(region.clone(), min, origin);
+            assert_eq!(
+                captured.to_region(origin, PasteOptions::default()),
+                (min, region)
+            );
+        }
+    }
+
+    #[test]
+    fn skip_air() {
+        let registry = test_registry();
+        let schematic = l_shape(&registry);
+        let dirt = registry.get_id("dirt").unwrap();
+        let blocks = [[[dirt; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+        let mut chunk = PalettedChunk::from_array(&blocks);
+        let (min, region) = schematic.to_region(BlockPos([4, 4, 4]), PasteOptions::default());
+        assert!(region.paste_into_chunk(min, ChunkPos([0, 0, 0]), &mut chunk, true));
+        assert_eq!(chunk.get(4, 4, 4), registry.get_id("stone").unwrap());
+        assert_eq!(chunk.get(5, 4, 5), dirt);
+        // Pasting the same blocks again doesn't modify anything
+        assert!(!region.paste_into_chunk(min, ChunkPos([0, 0, 0]), &mut chunk, true));
+        // Without skipping it, the air replaces the dirt
+        assert!(region.paste_into_chunk(min, ChunkPos([0, 0, 0]), &mut chunk, false));
+        assert_eq!(chunk.get(5, 4, 5), BlockId::from(0));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let registry = test_registry();
+        let mut schematic = l_shape(&registry);
+        schematic.origin = [1, -2, 3];
+        let path = std::env::temp_dir().join(format!("schematic-test-{}", std::process::id()));
+        schematic.save(&path, &registry).unwrap();
+        assert_eq!(Schematic::load(&path, &registry).unwrap(), schematic);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn load_structures() {
+        let registry = test_registry();
+        let ruin = Schematic::load(Path::new("assets/structures/ruin.schem"), &registry).unwrap();
+        assert_eq!(ruin.origin, [2, 0, 2]);
+        assert_eq!(
+            ruin.blocks.get([0, 0, 0]),
+            registry.get_id("stone").unwrap()
+        );
+    }
+}
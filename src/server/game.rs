@@ -1,41 +1,77 @@
 //! The game thread is the main server thread. It is authoritative over the game.
 
-use crate::block::{ChunkMap, ChunkPos, ChunkState};
+use crate::block::{
+    BlockId, BlockPos, BlockRegion, BlockRegistry, ChunkMap, ChunkPos, ChunkState, ServerChunk,
+};
 use crate::config::Config;
 use crate::core::messages::server::{ToGame, ToNetwork, ToWorldgen};
 use crate::network::ConnectionId;
-use crate::player::Player;
+use crate::player::{is_valid_player_name, MovementMode, Player, PlayerRecord, REACH_DISTANCE};
+use crate::server::worldgen::WorldGenerator;
 use crate::util::Ticker;
+use crate::world::World;
+use nalgebra::Vector3;
 use std::collections::HashMap;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+/// Updates sent to the players per second. The game waits at most one update for a message, so
+/// that it keeps ticking and saving when nothing happens.
+const UPDATE_RATE: u32 = 60;
 
 pub fn start(
     rx: Receiver<ToGame>,
     network_tx: Sender<ToNetwork>,
     worldgen_tx: Sender<ToWorldgen>,
+    generator: Box<dyn WorldGenerator>,
+    world: World,
     config: Arc<Config>,
+    block_registry: Arc<BlockRegistry>,
 ) {
-    let mut implementation = GameImpl::from_parts(rx, network_tx, worldgen_tx, config);
-    loop {
+    let mut implementation = GameImpl::from_parts(
+        rx,
+        network_tx,
+        worldgen_tx,
+        generator,
+        world,
+        config,
+        block_registry,
+    );
+    while implementation.running {
         implementation.process_messages();
 
         implementation.tick_game();
 
         implementation.send_chunks();
+
+        implementation.autosave();
     }
+    implementation.shutdown();
 }
 
 struct GameImpl {
+    running: bool,
     config: Arc<Config>,
+    block_registry: Arc<BlockRegistry>,
     rx: Receiver<ToGame>,
     network_tx: Sender<ToNetwork>,
     worldgen_tx: Sender<ToWorldgen>,
+    /// Generates the chunks that are needed right away, such as the chunks of a pasted region
+    generator: Box<dyn WorldGenerator>,
     chunks: ChunkMap,
+    /// Modified chunks that were unloaded since the last save. They are saved together so that
+    /// every region file is only rewritten once.
+    unsaved_chunks: HashMap<ChunkPos, ServerChunk>,
+    /// Version of the last chunk that was loaded, generated or modified
+    chunk_version: u64,
+    world: World,
     players: HashMap<ConnectionId, Player>,
+    /// Chunks the players were in when they were last sent to the worldgen threads
+    player_chunks: Vec<ChunkPos>,
     last_tick: Instant,
     last_update: Ticker,
+    last_save: Ticker,
 }
 
 impl GameImpl {
@@ -43,83 +79,319 @@ impl GameImpl {
         rx: Receiver<ToGame>,
         network_tx: Sender<ToNetwork>,
         worldgen_tx: Sender<ToWorldgen>,
+        generator: Box<dyn WorldGenerator>,
+        world: World,
         config: Arc<Config>,
+        block_registry: Arc<BlockRegistry>,
     ) -> Self {
+        let last_save =
+            Ticker::from_tick_duration(Duration::from_secs(config.autosave_interval.max(1)));
         Self {
+            running: true,
             config,
+            block_registry,
             rx,
             network_tx,
             worldgen_tx,
+            generator,
             chunks: HashMap::new(),
+            unsaved_chunks: HashMap::new(),
+            chunk_version: 0,
+            world,
             players: HashMap::new(),
+            player_chunks: Vec::new(),
             last_tick: Instant::now(),
-            last_update: Ticker::from_tick_rate(60),
+            last_update: Ticker::from_tick_rate(UPDATE_RATE),
+            last_save,
         }
     }
 
     pub fn process_messages(&mut self) {
-        let message = self.rx.recv().unwrap();
-        self.process_message(message);
+        match self
+            .rx
+            .recv_timeout(Duration::new(0, 1_000_000_000 / UPDATE_RATE))
+        {
+            Ok(message) => self.process_message(message),
+            Err(RecvTimeoutError::Timeout) => (),
+            // Nothing can stop the server anymore
+            Err(RecvTimeoutError::Disconnected) => self.running = false,
+        }
     }
 
     fn process_message(&mut self, message: ToGame) {
         use crate::core::messages::server::ToGamePlayer as Ev;
         match message {
             ToGame::PlayerEvent(id, ev) => match ev {
-                Ev::Connect => {
-                    self.players.insert(
-                        id,
-                        Player {
-                            pos: [
-                                self.config.player_x,
-                                self.config.player_y,
-                                self.config.player_z,
-                            ]
-                            .into(),
-                            yaw: 0.0,
-                            pitch: 0.0,
-                            render_distance: 0,
-                            chunks: HashMap::new(),
-                            keys: 0,
-                        },
-                    );
-                }
+                // The player appears when they log in
+                Ev::Connect => (),
+                Ev::Login(name) => self.login(id, name),
                 Ev::Disconnect => {
-                    self.players.remove(&id);
+                    if let Some(player) = self.players.remove(&id) {
+                        self.save_player(&player);
+                    }
+                    if let Err(e) = self.world.save_info() {
+                        println!("WARNING: failed to save the world info: {}", e);
+                    }
                 }
-                Ev::SetInput(input) => self.players.get_mut(&id).unwrap().set_input(&input),
-                Ev::SetRenderDistance(render_distance) => {
-                    self.players.get_mut(&id).unwrap().render_distance = render_distance
+                ev => {
+                    // Players who haven't logged in can't do anything
+                    let player = match self.players.get_mut(&id) {
+                        Some(player) => player,
+                        None => return,
+                    };
+                    match ev {
+                        Ev::SetInput(input) => player.set_input(&input),
+                        Ev::SetRenderDistance(render_distance) => {
+                            player.render_distance = render_distance
+                        }
+                        Ev::SetMovementMode(mode) => {
+                            if mode == MovementMode::Walk || self.config.allow_flying {
+                                player.mode = mode;
+                            } else {
+                                // The client already switched, so it must switch back
+                                self.network_tx
+                                    .send(ToNetwork::SetMovementMode(id, player.mode))
+                                    .unwrap();
+                            }
+                        }
+                        Ev::BreakBlock(pos) => self.update_block(id, pos, BlockId::from(0)),
+                        Ev::PlaceBlock(pos, block) => {
+                            if block != BlockId::from(0) {
+                                self.update_block(id, pos, block);
+                            }
+                        }
+                        Ev::Connect | Ev::Login(_) | Ev::Disconnect => unreachable!(),
+                    }
                 }
             },
-            ToGame::NewChunk(pos, c) => {
+            ToGame::NewChunk(pos, c, biomes) => {
+                // The chunk may have been dropped, or generated twice if it was requested again
                 if let Some(state) = self.chunks.get_mut(&pos) {
-                    *state = ChunkState::Generated(c);
+                    if let ChunkState::Generating = *state {
+                        self.chunk_version += 1;
+                        let chunk = ServerChunk::new(c, biomes, self.chunk_version);
+                        *state = ChunkState::Generated(chunk);
+                    }
+                }
+            }
+            // The sender may have stopped waiting for the reply
+            ToGame::CaptureRegion(origin, size, reply) => {
+                let _ = reply.send(self.capture_region(origin, size));
+            }
+            ToGame::PasteRegion(origin, region, skip_air, reply) => {
+                let _ = reply.send(self.paste_region(origin, &region, skip_air));
+            }
+            ToGame::Shutdown => self.running = false,
+        }
+    }
+
+    /// Restore the player from their record, or create them at the spawn point if they never
+    /// played in this world. Their position, orientation and movement mode are sent back to them.
+    fn login(&mut self, id: ConnectionId, name: String) {
+        let refusal = if self.players.contains_key(&id) {
+            Some("already logged in".to_owned())
+        } else if !is_valid_player_name(&name) {
+            Some(format!("invalid player name {:?}", name))
+        } else if self.players.values().any(|player| player.name == name) {
+            Some(format!("{} is already playing", name))
+        } else {
+            None
+        };
+        if let Some(reason) = refusal {
+            self.network_tx
+                .send(ToNetwork::Disconnect(id, reason))
+                .unwrap();
+            return;
+        }
+
+        let mut record = match self.world.load_player(&name) {
+            Ok(Some(record)) => record,
+            res => {
+                if let Err(e) = res {
+                    println!("WARNING: failed to load player {}: {}", name, e);
+                }
+                PlayerRecord {
+                    pos: self.world.info.spawn,
+                    yaw: 0.0,
+                    pitch: 0.0,
+                    mode: self.config.movement_mode,
                 }
             }
+        };
+        if !self.config.allow_flying {
+            record.mode = MovementMode::Walk;
         }
+        println!("{} logged in", name);
+        self.network_tx
+            .send(ToNetwork::Spawn(id, record.clone()))
+            .unwrap();
+        self.players.insert(id, Player::new(name, &record));
+    }
+
+    fn save_player(&self, player: &Player) {
+        if let Err(e) = self.world.save_player(&player.name, &player.record()) {
+            println!("WARNING: failed to save player {}: {}", player.name, e);
+        }
+    }
+
+    /// Replace a block on behalf of a player if they are allowed to, and send the new block to the
+    /// players who have the chunk. Only air can be replaced by a block, and only a block can be
+    /// replaced by air.
+    fn update_block(&mut self, id: ConnectionId, pos: BlockPos, block: BlockId) {
+        // The id comes from the client and may not exist
+        if !self.block_registry.contains(block) {
+            return;
+        }
+        let air = BlockId::from(0);
+        let player = match self.players.get(&id) {
+            Some(player) => player,
+            None => return,
+        };
+        // The block must be within reach
+        if (Vector3::from(pos.center()) - player.pos).norm() > REACH_DISTANCE + 1.0 {
+            return;
+        }
+
+        let chunk_pos = pos.chunk_pos();
+        let [x, y, z] = pos.pos_in_chunk();
+        let chunk = match self.chunks.get_mut(&chunk_pos) {
+            Some(ChunkState::Generated(chunk)) => chunk,
+            _ => return,
+        };
+        if (chunk.blocks.get(x, y, z) == air) == (block == air) {
+            return;
+        }
+        // Solid blocks can't be placed inside players
+        if self.block_registry.get_block(block).is_solid() {
+            for (_, player) in &self.players {
+                if player.mode != MovementMode::Noclip && player.aabb().intersects(pos) {
+                    return;
+                }
+            }
+        }
+        chunk.blocks.set(x, y, z, block);
+        chunk.dirty = true;
+        let old_version = chunk.version;
+        self.chunk_version += 1;
+        chunk.version = self.chunk_version;
+
+        // Players with an older version of the chunk will get the whole chunk again instead
+        for (&player_id, player) in &mut self.players {
+            if player.chunks.get(&chunk_pos) == Some(&old_version) {
+                self.network_tx
+                    .send(ToNetwork::BlockUpdate(player_id, pos, block))
+                    .unwrap();
+                player.chunks.insert(chunk_pos, self.chunk_version);
+            }
+        }
+    }
+
+    /// The chunk at `pos`, loaded from disk if needed. Chunks that were never generated, or
+    /// whose generation is not finished, are generated right away.
+    fn loaded_chunk(&mut self, pos: ChunkPos) -> Result<&mut ServerChunk, String> {
+        if let Some(c) = self.unsaved_chunks.remove(&pos) {
+            self.chunks.insert(pos, ChunkState::Generated(c));
+        }
+        let blocks = match self.chunks.get(&pos) {
+            Some(ChunkState::Generated(_)) => None,
+            Some(ChunkState::Generating) => {
+                // The worldgen threads would give the same chunk
+                self.worldgen_tx.send(ToWorldgen::CancelChunk(pos)).unwrap();
+                Some(self.generator.generate(pos))
+            }
+            None => match self.world.storage.load_chunk(pos) {
+                Ok(Some(c)) => Some(c),
+                Ok(None) => Some(self.generator.generate(pos)),
+                Err(e) => return Err(format!("failed to load chunk {:?}: {}", pos, e)),
+            },
+        };
+        if let Some(blocks) = blocks {
+            self.chunk_version += 1;
+            let biomes = self.generator.biomes(pos.0[0], pos.0[2]);
+            let chunk = ServerChunk::new(blocks, biomes, self.chunk_version);
+            self.chunks.insert(pos, ChunkState::Generated(chunk));
+        }
+        match self.chunks.get_mut(&pos) {
+            Some(ChunkState::Generated(c)) => Ok(c),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Copy the blocks of a region
+    fn capture_region(
+        &mut self,
+        origin: BlockPos,
+        size: [usize; 3],
+    ) -> Result<BlockRegion, String> {
+        let mut region = BlockRegion::new(size, BlockId::from(0));
+        for pos in region.chunks(origin) {
+            let chunk = self.loaded_chunk(pos)?;
+            region.copy_from_chunk(origin, pos, &chunk.blocks);
+        }
+        Ok(region)
+    }
+
+    /// Replace the blocks of a region, except where it has air if `skip_air` is set. Nothing
+    /// changes unless every chunk of the region can be loaded. The players get the modified chunks
+    /// again.
+    fn paste_region(
+        &mut self,
+        origin: BlockPos,
+        region: &BlockRegion,
+        skip_air: bool,
+    ) -> Result<(), String> {
+        let chunks = region.chunks(origin);
+        for &pos in &chunks {
+            self.loaded_chunk(pos)?;
+        }
+        for pos in chunks {
+            let version = self.chunk_version + 1;
+            let chunk = self.loaded_chunk(pos)?;
+            if region.paste_into_chunk(origin, pos, &mut chunk.blocks, skip_air) {
+                chunk.dirty = true;
+                chunk.version = version;
+                self.chunk_version = version;
+            }
+        }
+        Ok(())
     }
 
     pub fn tick_game(&mut self) {
         let now = Instant::now();
         let dt = now - self.last_tick;
         self.last_tick = now;
-        let dt = dt.subsec_nanos() as f64 / 1_000_000_000.0;
+        let dt = dt.as_secs() as f64 + dt.subsec_nanos() as f64 * 1e-9;
+        self.world.info.time += dt;
 
         for (_, p) in &mut self.players {
-            p.tick(dt, &self.config, &self.chunks);
+            p.tick(dt, &self.config, &self.chunks, &self.block_registry);
         }
     }
 
     pub fn send_chunks(&mut self) {
         let GameImpl {
             ref mut chunks,
+            ref mut unsaved_chunks,
+            ref mut chunk_version,
+            ref world,
+            ref mut generator,
             ref mut players,
+            ref mut player_chunks,
             ref mut network_tx,
+            ref worldgen_tx,
             ref mut last_update,
             ..
         } = *self;
 
+        // Generate the chunks that are the closest to the players first
+        let new_player_chunks: Vec<_> = players.values().map(|p| p.get_pos().chunk_pos()).collect();
+        if new_player_chunks != *player_chunks {
+            *player_chunks = new_player_chunks;
+            worldgen_tx
+                .send(ToWorldgen::SetPlayerChunks(player_chunks.clone()))
+                .unwrap();
+        }
+
         // Send chunks to the players, eventually generating them
         for (id, player) in players.iter_mut() {
             let mut nearby = Vec::new();
@@ -142,31 +414,47 @@ impl GameImpl {
                     pos.0[i] += pc.0[i];
                 }
 
-                // Entry manipulation
-                use std::collections::hash_map::Entry;
-                let player_entry = player.chunks.entry(pos);
-                if let Entry::Occupied(_) = player_entry {
-                    continue;
+                // Reload the chunk if it was unloaded but not saved yet
+                if let Some(c) = unsaved_chunks.remove(&pos) {
+                    chunks.insert(pos, ChunkState::Generated(c));
                 }
 
-                // At this point, the player doesn't have the chunk.
+                // Entry manipulation
+                use std::collections::hash_map::Entry;
                 match chunks.entry(pos) {
-                    Entry::Vacant(v) => {
+                    Entry::Vacant(v) => match world.storage.load_chunk(pos) {
+                        // Load it from disk
+                        Ok(Some(c)) => {
+                            *chunk_version += 1;
+                            let biomes = generator.biomes(pos.0[0], pos.0[2]);
+                            let chunk = ServerChunk::new(c, biomes, *chunk_version);
+                            v.insert(ChunkState::Generated(chunk));
+                        }
                         // Generate it
-                        v.insert(ChunkState::Generating);
-                        self.worldgen_tx
-                            .send(ToWorldgen::GenerateChunk(pos))
-                            .unwrap();
-                    }
+                        res => {
+                            if let Err(e) = res {
+                                println!("WARNING: failed to load chunk {:?}: {}", pos, e);
+                            }
+                            v.insert(ChunkState::Generating);
+                            worldgen_tx.send(ToWorldgen::GenerateChunk(pos)).unwrap();
+                        }
+                    },
                     Entry::Occupied(o) => match *o.get() {
                         // Wait until generated
                         ChunkState::Generating => (),
-                        // Send a copy of the chunk
+                        // Send a copy of the chunk if the player doesn't have this version
                         ChunkState::Generated(ref c) => {
-                            network_tx
-                                .send(ToNetwork::NewChunk(*id, pos, c.clone()))
-                                .unwrap();
-                            player_entry.or_insert(());
+                            if player.chunks.get(&pos) != Some(&c.version) {
+                                network_tx
+                                    .send(ToNetwork::NewChunk(
+                                        *id,
+                                        pos,
+                                        c.blocks.clone(),
+                                        c.biomes.clone(),
+                                    ))
+                                    .unwrap();
+                                player.chunks.insert(pos, c.version);
+                            }
                         }
                     },
                 }
@@ -178,14 +466,25 @@ impl GameImpl {
                 .retain(|pos, _| pos.orthogonal_dist(pc) <= render_distance);
         }
 
-        // Remove chunks that are far from all players
-        chunks.retain(|pos, _| {
+        // Remove chunks that are far from all players, saving them with the next batch or
+        // cancelling their generation
+        chunks.retain(|pos, state| {
             for (_, player) in players.iter() {
                 let p = player.get_pos();
                 if p.chunk_pos().orthogonal_dist(*pos) <= player.render_distance {
                     return true;
                 }
             }
+            match std::mem::replace(state, ChunkState::Generating) {
+                ChunkState::Generating => {
+                    worldgen_tx.send(ToWorldgen::CancelChunk(*pos)).unwrap();
+                }
+                ChunkState::Generated(c) => {
+                    if c.dirty {
+                        unsaved_chunks.insert(*pos, c);
+                    }
+                }
+            }
             false
         });
 
@@ -198,4 +497,48 @@ impl GameImpl {
             }
         }
     }
+
+    pub fn autosave(&mut self) {
+        if self.last_save.try_tick() {
+            self.save();
+        }
+    }
+
+    /// Save the modified chunks, the players and the world info
+    fn save(&mut self) {
+        let loaded = self.chunks.iter().filter_map(|(&pos, state)| match state {
+            ChunkState::Generated(c) if c.dirty => Some((pos, &c.blocks)),
+            _ => None,
+        });
+        let unloaded = self.unsaved_chunks.iter().map(|(&pos, c)| (pos, &c.blocks));
+        match self.world.storage.save_chunks(loaded.chain(unloaded)) {
+            Ok(()) => {
+                self.unsaved_chunks.clear();
+                for state in self.chunks.values_mut() {
+                    if let ChunkState::Generated(c) = state {
+                        if c.dirty {
+                            c.dirty = false;
+                            c.blocks.compact();
+                        }
+                    }
+                }
+            }
+            Err(e) => println!("WARNING: failed to save the chunks: {}", e),
+        }
+        for player in self.players.values() {
+            self.save_player(player);
+        }
+        if let Err(e) = self.world.save_info() {
+            println!("WARNING: failed to save the world info: {}", e);
+        }
+    }
+
+    /// Save everything and stop the other server threads
+    fn shutdown(&mut self) {
+        println!("Saving the world");
+        self.save();
+        // The other threads may have stopped already if they panicked
+        let _ = self.worldgen_tx.send(ToWorldgen::Shutdown);
+        let _ = self.network_tx.send(ToNetwork::Shutdown);
+    }
 }
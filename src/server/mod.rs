@@ -1,5 +1,151 @@
 //! Server-side threads.
 
+use crate::block::{BlockPos, BlockRegion, BlockRegistry};
+use crate::config::Config;
+use crate::core::messages::server::ToGame;
+use crate::network::Server;
+use crate::schematic::{PasteOptions, Schematic};
+use crate::world::World;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
 pub mod game;
 pub mod network;
 pub mod worldgen;
+
+/// The running server threads
+pub struct ServerHandle {
+    game_tx: Sender<ToGame>,
+    game: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    /// Save the world and stop the server, waiting until every thread is done
+    pub fn stop(self) {
+        // The game thread may have panicked
+        let _ = self.game_tx.send(ToGame::Shutdown);
+        self.join();
+    }
+
+    /// Wait until the server stops
+    pub fn join(self) {
+        self.game.join().unwrap();
+    }
+
+    /// Copy the blocks of the region of the given size whose lowest corner is at `origin`
+    pub fn capture_region(
+        &self,
+        origin: BlockPos,
+        size: [usize; 3],
+    ) -> Result<BlockRegion, String> {
+        let (tx, rx) = channel();
+        self.request(ToGame::CaptureRegion(origin, size, tx), rx)
+    }
+
+    /// Replace the blocks of a region whose lowest corner is at `origin`
+    pub fn paste_region(&self, origin: BlockPos, region: BlockRegion) -> Result<(), String> {
+        let (tx, rx) = channel();
+        self.request(ToGame::PasteRegion(origin, region, false, tx), rx)
+    }
+
+    /// Copy the blocks of the box of the given size whose lowest corner is at `min` into a
+    /// schematic whose origin is at `origin`
+    pub fn capture_schematic(
+        &self,
+        min: BlockPos,
+        size: [usize; 3],
+        origin: BlockPos,
+    ) -> Result<Schematic, String> {
+        let region = self.capture_region(min, size)?;
+        Ok(Schematic::from_region(region, min, origin))
+    }
+
+    /// Paste a schematic with its origin at `pos`
+    pub fn paste_schematic(
+        &self,
+        schematic: &Schematic,
+        pos: BlockPos,
+        options: PasteOptions,
+    ) -> Result<(), String> {
+        let (min, region) = schematic.to_region(pos, options);
+        let (tx, rx) = channel();
+        self.request(ToGame::PasteRegion(min, region, options.skip_air, tx), rx)
+    }
+
+    /// Send a message to the game thread and wait for its reply
+    fn request<T>(&self, message: ToGame, rx: Receiver<Result<T, String>>) -> Result<T, String> {
+        self.game_tx
+            .send(message)
+            .map_err(|_| "the server is stopped".to_owned())?;
+        rx.recv()
+            .unwrap_or_else(|_| Err("the server is stopped".to_owned()))
+    }
+}
+
+/// Open or create the world at `world_path` and start the server threads
+pub fn start(
+    server: impl Server + Send + 'static,
+    world_path: &Path,
+    config: Arc<Config>,
+    block_registry: Arc<BlockRegistry>,
+) -> ServerHandle {
+    let world = World::open(world_path, &config, &block_registry, |info| {
+        worldgen::create_generator(&info.generator, info.seed, &config, &block_registry)
+            .find_spawn()
+    })
+    .unwrap_or_else(|e| panic!("Failed to open world {}: {}", world_path.display(), e));
+    println!(
+        "Opened world {} ({} generator, seed {})",
+        world_path.display(),
+        world.info.generator,
+        world.info.seed
+    );
+
+    let (game_tx, game_rx) = channel();
+    let (network_tx, network_rx) = channel();
+    let (worldgen_tx, worldgen_rx) = channel();
+    let game_t = game_tx.clone();
+    let block_table = block_registry.block_table();
+    let network = thread::spawn(move || {
+        network::start(network_rx, game_t, server, block_table);
+    });
+    println!("Started server network thread");
+
+    // One more generator for the chunks the game thread needs right away
+    let mut generators: Vec<_> = (0..config.worldgen_threads.max(1) + 1)
+        .map(|_| {
+            worldgen::create_generator(
+                &world.info.generator,
+                world.info.seed,
+                &config,
+                &block_registry,
+            )
+        })
+        .collect();
+    let generator = generators.pop().unwrap();
+    let game_t = game_tx.clone();
+    let worldgen = thread::spawn(move || {
+        worldgen::start(worldgen_rx, game_t, generators);
+    });
+    println!("Started worldgen threads");
+
+    let game = thread::spawn(move || {
+        game::start(
+            game_rx,
+            network_tx,
+            worldgen_tx,
+            generator,
+            world,
+            config,
+            block_registry,
+        );
+        // The game thread stops the other threads when it stops
+        network.join().unwrap();
+        worldgen.join().unwrap();
+        println!("Stopped the server");
+    });
+    println!("Started game thread");
+    ServerHandle { game_tx, game }
+}
@@ -1,18 +1,26 @@
 //! The network thread manages client-server interaction.
 
+use crate::block::BlockTable;
 use crate::core::messages::network::{ToClient, ToServer};
 use crate::core::messages::server::{ToGame, ToGamePlayer, ToNetwork};
-use crate::network::{serialize_fragment, ConnectionId, Server};
+use crate::network::{
+    serialize_biomes, serialize_fragment, ConnectionId, Server, PROTOCOL_VERSION,
+};
 use crate::CHUNK_SIZE;
 
 use std::collections::{HashMap, VecDeque};
 use std::sync::mpsc::{Receiver, Sender};
 use std::time::Instant;
 
-pub fn start(rx: Receiver<ToNetwork>, game_tx: Sender<ToGame>, server: impl Server) {
-    let mut implementation = ServerImpl::from_parts(rx, game_tx, server);
+pub fn start(
+    rx: Receiver<ToNetwork>,
+    game_tx: Sender<ToGame>,
+    server: impl Server,
+    block_table: BlockTable,
+) {
+    let mut implementation = ServerImpl::from_parts(rx, game_tx, server, block_table);
 
-    loop {
+    while implementation.running {
         implementation.receive_messages();
 
         implementation.process_messages();
@@ -26,20 +34,30 @@ where
     rx: Receiver<ToNetwork>,
     game_tx: Sender<ToGame>,
     server: S,
+    /// Sent to every client when it connects
+    block_table: BlockTable,
     // TODO: either use this Instant or remove it
     queues: HashMap<ConnectionId, (Instant, VecDeque<ToNetwork>)>,
+    running: bool,
 }
 
 impl<S> ServerImpl<S>
 where
     S: Server,
 {
-    pub fn from_parts(rx: Receiver<ToNetwork>, game_tx: Sender<ToGame>, server: S) -> Self {
+    pub fn from_parts(
+        rx: Receiver<ToNetwork>,
+        game_tx: Sender<ToGame>,
+        server: S,
+        block_table: BlockTable,
+    ) -> Self {
         ServerImpl {
             rx,
             game_tx,
             server,
+            block_table,
             queues: HashMap::new(),
+            running: true,
         }
     }
 
@@ -48,37 +66,100 @@ where
         // Network messages
         while let Some(message) = self.server.next_event() {
             let message = match message {
-                ServerEvent::Connection(id) => Some((id, ToGamePlayer::Connect)),
-                ServerEvent::ConnectionClosed(id) => Some((id, ToGamePlayer::Disconnect)),
-                ServerEvent::Message(id, data) => Some((
-                    id,
-                    match bincode::deserialize(data.as_ref()).unwrap() {
-                        ToServer::SetInput(input) => ToGamePlayer::SetInput(input),
-                        ToServer::SetRenderDistance(render_distance) => {
-                            ToGamePlayer::SetRenderDistance(render_distance)
-                        }
-                    },
-                )),
+                ServerEvent::Connection(id) => {
+                    let handshake = ToClient::Handshake {
+                        protocol_version: PROTOCOL_VERSION,
+                        blocks: self.block_table.clone(),
+                    };
+                    self.server
+                        .send_message(id, bincode::serialize(&handshake).unwrap());
+                    Some((id, ToGamePlayer::Connect))
+                }
+                ServerEvent::ConnectionClosed(id) => {
+                    self.queues.remove(&id);
+                    Some((id, ToGamePlayer::Disconnect))
+                }
+                ServerEvent::Message(id, data) => match bincode::deserialize(data.as_ref()) {
+                    Ok(message) => Some((
+                        id,
+                        match message {
+                            ToServer::Login(name) => ToGamePlayer::Login(name),
+                            ToServer::SetInput(input) => ToGamePlayer::SetInput(input),
+                            ToServer::SetRenderDistance(render_distance) => {
+                                ToGamePlayer::SetRenderDistance(render_distance)
+                            }
+                            ToServer::SetMovementMode(mode) => ToGamePlayer::SetMovementMode(mode),
+                            ToServer::BreakBlock(pos) => ToGamePlayer::BreakBlock(pos),
+                            ToServer::PlaceBlock(pos, block) => {
+                                ToGamePlayer::PlaceBlock(pos, block)
+                            }
+                        },
+                    )),
+                    Err(e) => {
+                        // The client doesn't follow the protocol, so nothing it sends can be
+                        // trusted anymore. The game learns about it from `ConnectionClosed`.
+                        println!("WARNING: invalid message from client {}: {}", id, e);
+                        let reason = ToClient::Disconnect("Invalid message".to_owned());
+                        self.server
+                            .send_message(id, bincode::serialize(&reason).unwrap());
+                        self.server.disconnect(id);
+                        None
+                    }
+                },
             };
             if let Some(message) = message {
                 let message = ToGame::PlayerEvent(message.0, message.1);
-                self.game_tx.send(message).unwrap();
+                // The game thread may have stopped already if the server is shutting down
+                let _ = self.game_tx.send(message);
             }
         }
 
         // Internal messages
         while let Ok(message) = self.rx.try_recv() {
             let (queue, id) = match &message {
-                &ToNetwork::NewChunk(id, _, _) => {
+                &ToNetwork::NewChunk(id, _, _, _) => {
                     // Enqueue large message for later
                     (true, id)
                 }
+                &ToNetwork::BlockUpdate(id, _, _) => {
+                    // Enqueue the message so that it is sent after the chunk it modifies
+                    (true, id)
+                }
                 &ToNetwork::SetPos(id, pos) => {
                     // Instantly send the message because it is very important
                     self.server
                         .send_message(id, bincode::serialize(&ToClient::SetPos(pos)).unwrap());
                     (false, id)
                 }
+                &ToNetwork::SetMovementMode(id, mode) => {
+                    self.server.send_message(
+                        id,
+                        bincode::serialize(&ToClient::SetMovementMode(mode)).unwrap(),
+                    );
+                    (false, id)
+                }
+                &ToNetwork::Spawn(id, ref record) => {
+                    // Sent before the chunks so that the client knows where the player is
+                    self.server.send_message(
+                        id,
+                        bincode::serialize(&ToClient::Spawn(record.clone())).unwrap(),
+                    );
+                    (false, id)
+                }
+                &ToNetwork::Disconnect(id, ref reason) => {
+                    self.server.send_message(
+                        id,
+                        bincode::serialize(&ToClient::Disconnect(reason.clone())).unwrap(),
+                    );
+                    // The reason is sent before the connection is closed
+                    self.server.disconnect(id);
+                    self.queues.remove(&id);
+                    (false, id)
+                }
+                &ToNetwork::Shutdown => {
+                    self.running = false;
+                    return;
+                }
             };
             if queue {
                 self.queues
@@ -96,12 +177,18 @@ where
             if queue.len() > 0 {
                 // Reply to 1 message
                 match queue.pop_front().unwrap() {
-                    ToNetwork::NewChunk(_, pos, chunk) => {
+                    ToNetwork::NewChunk(_, pos, chunk, biomes) => {
                         //println!("[Server] Network: processing chunk @ {:?}", pos);
+                        let biomes = serialize_biomes(&biomes);
+                        self.server.send_message(
+                            *id,
+                            bincode::serialize(&ToClient::NewChunk(pos, biomes)).unwrap(),
+                        );
 
                         let mut info = [0; CHUNK_SIZE * CHUNK_SIZE / 32];
-                        for (cx, chunkyz) in chunk.iter().enumerate() {
-                            'yiter: for (cy, chunkz) in chunkyz.iter().enumerate() {
+                        for cx in 0..CHUNK_SIZE {
+                            'yiter: for cy in 0..CHUNK_SIZE {
+                                let chunkz = chunk.get_fragment(cx, cy);
                                 for block in chunkz.iter() {
                                     // Only send the message if the ChunkFragment is not empty.
                                     if block.0 != 0 {
@@ -122,15 +209,27 @@ where
                                     }
                                 }
                             }
-                            self.server.send_message(
-                                *id,
-                                bincode::serialize(&ToClient::NewChunkInfo(pos, info)).unwrap(),
-                            );
                         }
+                        self.server.send_message(
+                            *id,
+                            bincode::serialize(&ToClient::NewChunkInfo(pos, info)).unwrap(),
+                        );
+                    }
+                    ToNetwork::BlockUpdate(_, pos, block) => {
+                        self.server.send_message(
+                            *id,
+                            bincode::serialize(&ToClient::BlockUpdate(pos, block)).unwrap(),
+                        );
                     }
-                    ToNetwork::SetPos(_, _) => unreachable!(),
+                    ToNetwork::SetPos(..)
+                    | ToNetwork::SetMovementMode(..)
+                    | ToNetwork::Spawn(..)
+                    | ToNetwork::Disconnect(..)
+                    | ToNetwork::Shutdown => unreachable!(),
                 }
             }
         }
+        // Messages may still arrive for clients that left after they were enqueued
+        self.queues.retain(|_, (_, queue)| !queue.is_empty());
     }
 }
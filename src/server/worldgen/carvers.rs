@@ -0,0 +1,287 @@
+//! Stages carving holes in the terrain.
+
+use super::terrain::TerrainShape;
+use super::{get_block_id, Stage, WorldgenRng};
+use crate::block::{BlockId, BlockPos, BlockRegistry, ChunkArray, ChunkPos};
+use crate::util::{mix_seed, LruCache};
+use crate::CHUNK_SIZE;
+
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Average number of caves starting in a chunk
+const WORM_FREQUENCY: f64 = 0.15;
+/// Maximum number of steps of the random walk of a cave
+const WORM_LENGTH: usize = 64;
+/// Radius of the caves, in blocks
+const WORM_MIN_RADIUS: f64 = 1.5;
+const WORM_MAX_RADIUS: f64 = 3.5;
+/// Caves can't reach chunks that are further than this from the chunk they start in
+const WORM_RANGE: i64 =
+    ((WORM_LENGTH as f64 + WORM_MAX_RADIUS) as i64 + CHUNK_SIZE as i64) / CHUNK_SIZE as i64;
+
+/// Center and radius of the spheres of a cave
+type Worm = Vec<([f64; 3], f64)>;
+
+/// Caves starting in the chunks of a column of chunks, computed when they are first needed
+type CaveColumn = Mutex<HashMap<i64, Arc<Worm>>>;
+
+/// Carves worm-like caves: spheres of air along random walks. A cave can cross many chunks, so
+/// every chunk replays the caves of the chunks within `WORM_RANGE` and only carves its own
+/// blocks. Later stages can find out which blocks are carved the same way.
+pub struct WormCarver {
+    seed: u64,
+    air: BlockId,
+    /// `None` if the cache is disabled
+    cache: Option<Mutex<LruCache<(i64, i64), Arc<CaveColumn>>>>,
+}
+
+impl WormCarver {
+    /// Create the carver, caching the caves of up to `cache_size` columns of chunks
+    pub fn new(seed: u64, cache_size: usize, block_registry: &BlockRegistry) -> Self {
+        Self {
+            seed,
+            air: get_block_id(block_registry, "air"),
+            cache: if cache_size > 0 {
+                Some(Mutex::new(LruCache::new(cache_size)))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// The cave that starts in `start_chunk`, empty if there is none
+    fn worm(&self, start_chunk: ChunkPos) -> Arc<Worm> {
+        let cache = match self.cache {
+            Some(ref cache) => cache,
+            None => return Arc::new(self.compute_worm(start_chunk)),
+        };
+        let [cx, cy, cz] = start_chunk.0;
+        let column = {
+            let mut cache = cache.lock().unwrap();
+            match cache.get(&(cx, cz)) {
+                Some(column) => column,
+                None => {
+                    let column = Arc::new(Mutex::new(HashMap::new()));
+                    cache.insert((cx, cz), column.clone());
+                    column
+                }
+            }
+        };
+        let mut column = column.lock().unwrap();
+        column
+            .entry(cy)
+            .or_insert_with(|| Arc::new(self.compute_worm(start_chunk)))
+            .clone()
+    }
+
+    /// Compute the result of `worm`
+    fn compute_worm(&self, start_chunk: ChunkPos) -> Worm {
+        let mut rng = WorldgenRng::seed_from_u64(mix_seed(self.seed, &start_chunk.0));
+        if rng.gen::<f64>() >= WORM_FREQUENCY {
+            return Vec::new();
+        }
+
+        let mut position = [0.0; 3];
+        for i in 0..3 {
+            position[i] = (start_chunk.0[i] * CHUNK_SIZE as i64) as f64
+                + rng.gen_range(0.0, CHUNK_SIZE as f64);
+        }
+        let mut direction = random_direction(&mut rng);
+        let length = rng.gen_range(WORM_LENGTH / 2, WORM_LENGTH);
+        let mut radius = rng.gen_range(WORM_MIN_RADIUS, WORM_MAX_RADIUS);
+        let mut spheres = Vec::with_capacity(length);
+        for _ in 0..length {
+            spheres.push((position, radius));
+
+            // Turn slowly, and mostly horizontally
+            let turn = random_direction(&mut rng);
+            for i in 0..3 {
+                direction[i] += 0.3 * turn[i];
+            }
+            direction[1] *= 0.7;
+            normalize(&mut direction);
+            for i in 0..3 {
+                position[i] += direction[i];
+            }
+            radius += rng.gen_range(-0.2, 0.2);
+            radius = f64::max(WORM_MIN_RADIUS, f64::min(WORM_MAX_RADIUS, radius));
+        }
+        spheres
+    }
+
+    /// Whether the blocks of a column, from `min_y` (included) to `max_y` (excluded), are carved
+    pub fn carved_column(&self, x: i64, z: i64, min_y: i64, max_y: i64) -> Vec<bool> {
+        let mut carved = vec![false; (max_y - min_y) as usize];
+        let [cx, min_cy, cz] = BlockPos([x, min_y, z]).chunk_pos().0;
+        let max_cy = BlockPos([x, max_y - 1, z]).chunk_pos().0[1];
+        for dx in -WORM_RANGE..=WORM_RANGE {
+            for cy in min_cy - WORM_RANGE..=max_cy + WORM_RANGE {
+                for dz in -WORM_RANGE..=WORM_RANGE {
+                    for &(center, radius) in self.worm(ChunkPos([cx + dx, cy, cz + dz])).iter() {
+                        // Same bounds and test as `carve_sphere`
+                        let low = i64::max(min_y, (center[1] - radius).floor() as i64);
+                        let high = i64::min(max_y, (center[1] + radius).ceil() as i64 + 1);
+                        for y in low..high {
+                            let d = [
+                                x as f64 + 0.5 - center[0],
+                                y as f64 + 0.5 - center[1],
+                                z as f64 + 0.5 - center[2],
+                            ];
+                            if d[0] * d[0] + d[1] * d[1] + d[2] * d[2] <= radius * radius {
+                                carved[(y - min_y) as usize] = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        carved
+    }
+
+    /// Height of the highest block of a column that is still solid once the caves are carved
+    pub fn top_solid_block(&self, shape: &TerrainShape, x: i64, z: i64) -> i64 {
+        // Caves only remove blocks, so the highest block is at most the surface of the terrain
+        let mut max_y = shape.surface_height(x, z) + 1;
+        loop {
+            let min_y = max_y - CHUNK_SIZE as i64;
+            let solid = shape.solid_column(x, z, min_y, max_y);
+            let carved = self.carved_column(x, z, min_y, max_y);
+            for k in (0..CHUNK_SIZE).rev() {
+                if solid[k] && !carved[k] {
+                    return min_y + k as i64;
+                }
+            }
+            max_y = min_y;
+        }
+    }
+
+    /// Replace the blocks of the chunk at `pos` whose center is inside a sphere with air
+    fn carve_sphere(&self, chunk: &mut ChunkArray, pos: ChunkPos, center: [f64; 3], radius: f64) {
+        let mut min = [0; 3];
+        let mut max = [0; 3];
+        for i in 0..3 {
+            let chunk_min = pos.0[i] * CHUNK_SIZE as i64;
+            min[i] = i64::max(chunk_min, (center[i] - radius).floor() as i64);
+            max[i] = i64::min(
+                chunk_min + CHUNK_SIZE as i64,
+                (center[i] + radius).ceil() as i64 + 1,
+            );
+        }
+        for x in min[0]..max[0] {
+            for y in min[1]..max[1] {
+                for z in min[2]..max[2] {
+                    let d = [
+                        x as f64 + 0.5 - center[0],
+                        y as f64 + 0.5 - center[1],
+                        z as f64 + 0.5 - center[2],
+                    ];
+                    if d[0] * d[0] + d[1] * d[1] + d[2] * d[2] <= radius * radius {
+                        let [cx, cy, cz] = pos.0;
+                        chunk[(x - cx * CHUNK_SIZE as i64) as usize]
+                            [(y - cy * CHUNK_SIZE as i64) as usize]
+                            [(z - cz * CHUNK_SIZE as i64) as usize] = self.air;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A random unit vector. It doesn't use trigonometry, which isn't deterministic across
+/// platforms.
+fn random_direction(rng: &mut impl Rng) -> [f64; 3] {
+    loop {
+        let mut v = [0.0; 3];
+        for c in v.iter_mut() {
+            *c = rng.gen_range(-1.0, 1.0);
+        }
+        let norm2 = v[0] * v[0] + v[1] * v[1] + v[2] * v[2];
+        if norm2 > 1e-3 && norm2 <= 1.0 {
+            normalize(&mut v);
+            return v;
+        }
+    }
+}
+
+fn normalize(v: &mut [f64; 3]) {
+    let norm = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if norm > 0.0 {
+        for c in v.iter_mut() {
+            *c /= norm;
+        }
+    }
+}
+
+impl Stage for WormCarver {
+    fn apply(&self, chunk: &mut ChunkArray, pos: ChunkPos) {
+        for dx in -WORM_RANGE..=WORM_RANGE {
+            for dy in -WORM_RANGE..=WORM_RANGE {
+                for dz in -WORM_RANGE..=WORM_RANGE {
+                    let start_chunk = ChunkPos([pos.0[0] + dx, pos.0[1] + dy, pos.0[2] + dz]);
+                    for &(center, radius) in self.worm(start_chunk).iter() {
+                        self.carve_sphere(chunk, pos, center, radius);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{create_headless_block_registry, load_block_definitions};
+    use std::collections::HashSet;
+    use std::path::Path;
+
+    #[test]
+    fn caves_cross_chunk_borders() {
+        let definitions = load_block_definitions(Path::new("assets/blocks.toml"));
+        let registry = create_headless_block_registry(&definitions);
+        let stone = get_block_id(&registry, "stone");
+        let carver = WormCarver::new(7, 16, &registry);
+
+        // Carve every chunk of a region separately
+        let mut air = HashSet::new();
+        for cx in 0..4 {
+            for cy in 0..4 {
+                for cz in 0..4 {
+                    let mut chunk = [[[stone; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+                    carver.apply(&mut chunk, ChunkPos([cx, cy, cz]));
+                    for i in 0..CHUNK_SIZE {
+                        for j in 0..CHUNK_SIZE {
+                            for k in 0..CHUNK_SIZE {
+                                if chunk[i][j][k] == carver.air {
+                                    air.insert([
+                                        cx * CHUNK_SIZE as i64 + i as i64,
+                                        cy * CHUNK_SIZE as i64 + j as i64,
+                                        cz * CHUNK_SIZE as i64 + k as i64,
+                                    ]);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // Some caves go from a chunk to the next one
+        assert!(air.iter().any(|&[x, y, z]| {
+            (x + 1) % CHUNK_SIZE as i64 == 0
+                && x + 1 < 4 * CHUNK_SIZE as i64
+                && air.contains(&[x + 1, y, z])
+        }));
+
+        // The columns give the same carved blocks as the chunks
+        for x in (0..4 * CHUNK_SIZE as i64).step_by(3) {
+            for z in (0..4 * CHUNK_SIZE as i64).step_by(5) {
+                let carved = carver.carved_column(x, z, 0, 4 * CHUNK_SIZE as i64);
+                for (y, &carved) in carved.iter().enumerate() {
+                    assert_eq!(carved, air.contains(&[x, y as i64, z]));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Stages placing features, such as trees, on top of the terrain.
+
+use super::carvers::WormCarver;
+use super::terrain::TerrainShape;
+use super::{get_block_id, Stage, WorldgenRng};
+use crate::block::{BlockId, BlockPos, BlockRegistry, ChunkArray, ChunkPos};
+use crate::util::mix_seed;
+use crate::CHUNK_SIZE;
+
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+
+/// Horizontal distance between the trunk of a tree and its furthest leaves. It must be smaller
+/// than `CHUNK_SIZE` because only the trees of adjacent columns are placed in a chunk.
+const TREE_RADIUS: i64 = 2;
+
+/// Number of random tree positions in a column of chunks. Each of them gets a tree depending on
+/// the tree density of its biome.
+const TREE_CANDIDATES: usize = 8;
+
+/// Grows trees depending on the tree density of the biomes
+pub struct TreeFeature {
+    seed: u64,
+    shape: Arc<TerrainShape>,
+    carver: Arc<WormCarver>,
+    air: BlockId,
+    wood: BlockId,
+    leaves: BlockId,
+}
+
+impl TreeFeature {
+    pub fn new(
+        seed: u64,
+        shape: Arc<TerrainShape>,
+        carver: Arc<WormCarver>,
+        block_registry: &BlockRegistry,
+    ) -> Self {
+        Self {
+            seed,
+            shape,
+            carver,
+            air: get_block_id(block_registry, "air"),
+            wood: get_block_id(block_registry, "wood"),
+            leaves: get_block_id(block_registry, "leaves"),
+        }
+    }
+
+    /// Positions of the lowest trunk blocks of the trees of a column of chunks
+    fn tree_positions(&self, cx: i64, cz: i64) -> Vec<[i64; 3]> {
+        let mut rng = WorldgenRng::seed_from_u64(mix_seed(self.seed, &[cx, cz]));
+        let mut trees = Vec::new();
+        for _ in 0..TREE_CANDIDATES {
+            // Always draw the same numbers so that a candidate doesn't depend on the previous ones
+            let x = cx * CHUNK_SIZE as i64 + rng.gen_range(0, CHUNK_SIZE as i64);
+            let z = cz * CHUNK_SIZE as i64 + rng.gen_range(0, CHUNK_SIZE as i64);
+            let chance: f64 = rng.gen();
+            if chance < self.shape.biome(x, z).properties().tree_density {
+                trees.push([x, self.carver.top_solid_block(&self.shape, x, z), z]);
+            }
+        }
+        trees
+    }
+
+    /// Place the blocks of a tree that are inside the chunk at `pos`. The trunk replaces any
+    /// block but the leaves only replace air, so overlapping trees give the same blocks whatever
+    /// order they are placed in.
+    fn place_tree(&self, chunk: &mut ChunkArray, pos: ChunkPos, trunk: [i64; 3]) {
+        let mut set = |dx: i64, dy: i64, dz: i64, block: BlockId| {
+            let block_pos = BlockPos([trunk[0] + dx, trunk[1] + dy, trunk[2] + dz]);
+            if block_pos.chunk_pos() == pos {
+                let [x, y, z] = block_pos.pos_in_chunk();
+                if block != self.leaves || chunk[x][y][z] == self.air {
+                    chunk[x][y][z] = block;
+                }
+            }
+        };
+
+        for dx in -TREE_RADIUS..=TREE_RADIUS {
+            for dz in -TREE_RADIUS..=TREE_RADIUS {
+                let layers = match dx.abs() + dz.abs() {
+                    0 | 1 => 6,
+                    2 => 5,
+                    _ => 3,
+                };
+                for dy in 0..layers {
+                    set(dx, dy + 3, dz, self.leaves);
+                }
+            }
+        }
+        for dy in 0..7 {
+            set(0, dy, 0, self.wood);
+        }
+    }
+}
+
+impl Stage for TreeFeature {
+    fn apply(&self, chunk: &mut ChunkArray, pos: ChunkPos) {
+        // Include the trees of the neighbouring columns that extend into this chunk
+        for dx in -1..=1 {
+            for dz in -1..=1 {
+                for trunk in self.tree_positions(pos.0[0] + dx, pos.0[2] + dz) {
+                    self.place_tree(chunk, pos, trunk);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Superflat terrain, made of horizontal layers of blocks.
+
+use super::Stage;
+use crate::block::{BlockId, BlockRegistry, ChunkArray, ChunkPos};
+use crate::CHUNK_SIZE;
+
+/// Parse a list of layers such as `stone,3*dirt,grass`, from the bottom to the top. `n*block`
+/// stands for `n` layers of `block`. Returns the block of every layer.
+pub fn parse_flat_layers(
+    layers: &str,
+    block_registry: &BlockRegistry,
+) -> Result<Vec<BlockId>, String> {
+    let mut blocks = Vec::new();
+    for layer in layers.split(',').map(str::trim).filter(|l| !l.is_empty()) {
+        let (count, name) = match layer.find('*') {
+            Some(star) => {
+                let count = layer[..star].trim();
+                let count = count
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid layer count {} in {}", count, layer))?;
+                (count, layer[star + 1..].trim())
+            }
+            None => (1, layer),
+        };
+        let block = block_registry
+            .get_id(name)
+            .ok_or_else(|| format!("unknown block {}", name))?;
+        blocks.extend(std::iter::repeat(block).take(count));
+    }
+    Ok(blocks)
+}
+
+/// Places horizontal layers of blocks. The top layer is at height 0, so that the surface of a
+/// flat world is at the same height as the average surface of the default worlds.
+pub struct FlatStage {
+    /// Block of every layer, from the bottom to the top
+    layers: Vec<BlockId>,
+}
+
+impl FlatStage {
+    pub fn new(layers: Vec<BlockId>) -> Self {
+        Self { layers }
+    }
+
+    /// Block of the layer at height `y`, if there is one
+    fn layer(&self, y: i64) -> Option<BlockId> {
+        let index = y + self.layers.len() as i64 - 1;
+        if index >= 0 && index < self.layers.len() as i64 {
+            Some(self.layers[index as usize])
+        } else {
+            None
+        }
+    }
+}
+
+impl Stage for FlatStage {
+    fn apply(&self, chunk: &mut ChunkArray, pos: ChunkPos) {
+        let min_y = pos.0[1] * CHUNK_SIZE as i64;
+        for k in 0..CHUNK_SIZE {
+            if let Some(block) = self.layer(min_y + k as i64) {
+                for i in 0..CHUNK_SIZE {
+                    for j in 0..CHUNK_SIZE {
+                        chunk[i][k][j] = block;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Pipeline, WorldGenerator};
+    use super::*;
+    use crate::block::{create_headless_block_registry, load_block_definitions};
+    use std::path::Path;
+
+    fn registry() -> BlockRegistry {
+        create_headless_block_registry(&load_block_definitions(Path::new("assets/blocks.toml")))
+    }
+
+    #[test]
+    fn parse_layers() {
+        let registry = registry();
+        let id = |name| registry.get_id(name).unwrap();
+        assert_eq!(
+            parse_flat_layers("stone, 3*dirt,grass", &registry).unwrap(),
+            vec![id("stone"), id("dirt"), id("dirt"), id("dirt"), id("grass")]
+        );
+        assert_eq!(parse_flat_layers("", &registry).unwrap(), vec![]);
+        assert!(parse_flat_layers("x*dirt", &registry).is_err());
+        assert!(parse_flat_layers("bedrock", &registry).is_err());
+    }
+
+    #[test]
+    fn flat_chunks() {
+        let registry = registry();
+        let id = |name| registry.get_id(name).unwrap();
+        let mut pipeline = Pipeline::new(&registry);
+        pipeline.add_stage(FlatStage::new(
+            parse_flat_layers("stone,3*dirt,grass", &registry).unwrap(),
+        ));
+
+        let chunk = pipeline.generate(ChunkPos([3, -1, -7]));
+        let expected = |y: i64| match y {
+            -4 => id("stone"),
+            -3..=-1 => id("dirt"),
+            0 => id("grass"),
+            _ => id("air"),
+        };
+        for y in 0..CHUNK_SIZE {
+            assert_eq!(chunk.get(5, y, 9), expected(y as i64 - CHUNK_SIZE as i64));
+        }
+        let chunk = pipeline.generate(ChunkPos([0, 0, 0]));
+        assert_eq!(chunk.get(31, 0, 0), id("grass"));
+        assert_eq!(chunk.uniform_block(), None);
+        assert_eq!(
+            pipeline.generate(ChunkPos([0, 1, 0])).uniform_block(),
+            Some(id("air"))
+        );
+    }
+}
@@ -0,0 +1,445 @@
+//! The worldgen threads generates chunks.
+//! It it used to offload computation-intensive operations from the game thread.
+//! Requests are queued, and a pool of workers generates the requested chunks that are the
+//! closest to a player first.
+//! Generation only depends on the world seed and on the position of the chunk, so that a seed
+//! always produces the same world. For this reason it must not use platform-dependent floating
+//! point functions such as `sin` or `cos`.
+//!
+//! A world generator is usually a `Pipeline` of stages, each of them modifying the blocks
+//! produced by the previous ones.
+
+use crate::biome::{Biome, ChunkBiomes};
+use crate::block::{BlockId, BlockRegistry, ChunkArray, ChunkPos, PalettedChunk};
+use crate::config::Config;
+use crate::core::messages::server::{ToGame, ToWorldgen};
+use crate::CHUNK_SIZE;
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+pub mod carvers;
+pub mod features;
+pub mod flat;
+pub mod ores;
+pub mod preview;
+pub mod structures;
+pub mod surface;
+pub mod terrain;
+
+use self::carvers::WormCarver;
+use self::features::TreeFeature;
+use self::flat::{parse_flat_layers, FlatStage};
+use self::ores::{load_ore_definitions, OreStage};
+use self::structures::{load_structure_definitions, StructureStage};
+use self::surface::SurfaceStage;
+use self::terrain::{DensitySettings, TerrainShape, TerrainStage};
+
+/// Random number generator of the stages. Unlike `StdRng`, its algorithm is the same on every
+/// platform and will never change, so that the same seed keeps giving the same world.
+pub type WorldgenRng = rand_pcg::Pcg32;
+
+/// Start a worker for every generator, and queue the requests of the game thread until it
+/// stops or shuts the worldgen threads down
+pub fn start(
+    rx: Receiver<ToWorldgen>,
+    game_tx: Sender<ToGame>,
+    generators: Vec<Box<dyn WorldGenerator>>,
+) {
+    let queue = Arc::new((Mutex::new(ChunkQueue::new()), Condvar::new()));
+    let mut workers = Vec::new();
+    for generator in generators {
+        let queue = queue.clone();
+        let game_tx = game_tx.clone();
+        workers.push(thread::spawn(move || {
+            run_worker(&queue, &game_tx, generator);
+        }));
+    }
+
+    for message in rx {
+        let (ref queue, ref condvar) = *queue;
+        let mut queue = queue.lock().unwrap();
+        match message {
+            ToWorldgen::GenerateChunk(pos) => {
+                queue.pending.insert(pos);
+                condvar.notify_one();
+            }
+            ToWorldgen::CancelChunk(pos) => {
+                queue.pending.remove(&pos);
+            }
+            ToWorldgen::SetPlayerChunks(player_chunks) => queue.player_chunks = player_chunks,
+            ToWorldgen::Shutdown => break,
+        }
+    }
+
+    // The game thread stopped
+    queue.0.lock().unwrap().running = false;
+    queue.1.notify_all();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+/// Chunks waiting to be generated
+struct ChunkQueue {
+    pending: HashSet<ChunkPos>,
+    player_chunks: Vec<ChunkPos>,
+    running: bool,
+}
+
+impl ChunkQueue {
+    fn new() -> Self {
+        Self {
+            pending: HashSet::new(),
+            player_chunks: Vec::new(),
+            running: true,
+        }
+    }
+
+    /// Remove the pending chunk that is the closest to a player
+    fn pop_closest(&mut self) -> Option<ChunkPos> {
+        let player_chunks = &self.player_chunks;
+        let distance = |pos: &ChunkPos| {
+            player_chunks
+                .iter()
+                .map(|player| {
+                    let d = [
+                        pos.0[0] - player.0[0],
+                        pos.0[1] - player.0[1],
+                        pos.0[2] - player.0[2],
+                    ];
+                    d[0] * d[0] + d[1] * d[1] + d[2] * d[2]
+                })
+                .min()
+                .unwrap_or(0)
+        };
+        let closest = self.pending.iter().cloned().min_by_key(distance)?;
+        self.pending.remove(&closest);
+        Some(closest)
+    }
+}
+
+fn run_worker(
+    queue: &(Mutex<ChunkQueue>, Condvar),
+    game_tx: &Sender<ToGame>,
+    mut generator: Box<dyn WorldGenerator>,
+) {
+    let (ref queue, ref condvar) = *queue;
+    loop {
+        let pos = {
+            let mut queue = queue.lock().unwrap();
+            loop {
+                if !queue.running {
+                    return;
+                }
+                if let Some(pos) = queue.pop_closest() {
+                    break pos;
+                }
+                queue = condvar.wait(queue).unwrap();
+            }
+        };
+        let blocks = generator.generate(pos);
+        let biomes = generator.biomes(pos.0[0], pos.0[2]);
+        if game_tx.send(ToGame::NewChunk(pos, blocks, biomes)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Something that generates the chunks of a world
+pub trait WorldGenerator: Send {
+    fn generate(&mut self, pos: ChunkPos) -> PalettedChunk;
+
+    /// Biomes of every column of the chunks at horizontal position (cx, cz)
+    fn biomes(&mut self, cx: i64, cz: i64) -> Box<ChunkBiomes>;
+
+    /// Position where the players of a new world appear: on top of the highest block near the
+    /// origin, or at the origin if there is nothing but air
+    fn find_spawn(&mut self) -> [f64; 3] {
+        let air = BlockId::from(0);
+        for cy in (SPAWN_MIN_Y..SPAWN_MAX_Y).rev() {
+            let chunk = self.generate(ChunkPos([0, cy, 0]));
+            if let Some(y) = (0..CHUNK_SIZE).rev().find(|&y| chunk.get(0, y, 0) != air) {
+                let height = cy * CHUNK_SIZE as i64 + y as i64;
+                return [0.5, height as f64 + 1.0, 0.5];
+            }
+        }
+        [0.5, 0.0, 0.5]
+    }
+}
+
+/// Vertical range of the chunks searched for the spawn point, in chunks
+const SPAWN_MIN_Y: i64 = -4;
+const SPAWN_MAX_Y: i64 = 8;
+
+/// A step of a `Pipeline`
+pub trait Stage: Send {
+    /// Modify the blocks of the chunk at `pos`
+    fn apply(&self, chunk: &mut ChunkArray, pos: ChunkPos);
+}
+
+/// A stage that later stages also query, such as the carver
+impl<S: Stage + Sync> Stage for Arc<S> {
+    fn apply(&self, chunk: &mut ChunkArray, pos: ChunkPos) {
+        S::apply(self, chunk, pos)
+    }
+}
+
+/// Generates chunks by applying stages, in order, to chunks full of air
+pub struct Pipeline {
+    air: BlockId,
+    stages: Vec<Box<dyn Stage>>,
+    /// Terrain giving the biomes of the columns. Without it, every column is plains.
+    shape: Option<Arc<TerrainShape>>,
+}
+
+impl Pipeline {
+    pub fn new(block_registry: &BlockRegistry) -> Self {
+        Self {
+            air: get_block_id(block_registry, "air"),
+            stages: Vec::new(),
+            shape: None,
+        }
+    }
+
+    pub fn add_stage(&mut self, stage: impl Stage + 'static) {
+        self.stages.push(Box::new(stage));
+    }
+
+    /// Take the biomes of the columns from `shape`
+    pub fn set_terrain(&mut self, shape: Arc<TerrainShape>) {
+        self.shape = Some(shape);
+    }
+}
+
+impl WorldGenerator for Pipeline {
+    fn generate(&mut self, pos: ChunkPos) -> PalettedChunk {
+        let mut chunk = [[[self.air; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+        for stage in &self.stages {
+            stage.apply(&mut chunk, pos);
+        }
+        PalettedChunk::from_array(&chunk)
+    }
+
+    fn biomes(&mut self, cx: i64, cz: i64) -> Box<ChunkBiomes> {
+        let mut biomes = Box::new([[Biome::Plains; CHUNK_SIZE]; CHUNK_SIZE]);
+        if let Some(ref shape) = self.shape {
+            for i in 0..CHUNK_SIZE {
+                for j in 0..CHUNK_SIZE {
+                    biomes[i][j] = shape.biome(
+                        cx * CHUNK_SIZE as i64 + i as i64,
+                        cz * CHUNK_SIZE as i64 + j as i64,
+                    );
+                }
+            }
+        }
+        biomes
+    }
+}
+
+/// Names of the generators that `create_generator` knows
+pub const GENERATORS: [&str; 3] = ["default", "flat", "void"];
+
+/// Create the generator called `name`, configured by `config`. Panics if there is no such
+/// generator.
+pub fn create_generator(
+    name: &str,
+    seed: u64,
+    config: &Config,
+    block_registry: &BlockRegistry,
+) -> Box<dyn WorldGenerator> {
+    match name {
+        "default" => {
+            let settings = DensitySettings {
+                octaves: config.terrain_octaves,
+                warp: config.terrain_warp,
+            };
+            Box::new(default_generator(
+                seed,
+                settings,
+                config.worldgen_column_cache,
+                block_registry,
+            ))
+        }
+        "flat" => {
+            let layers = parse_flat_layers(&config.flat_layers, block_registry)
+                .unwrap_or_else(|e| panic!("Invalid flat_layers {}: {}", config.flat_layers, e));
+            let mut pipeline = Pipeline::new(block_registry);
+            pipeline.add_stage(FlatStage::new(layers));
+            Box::new(pipeline)
+        }
+        // Nothing but air
+        "void" => Box::new(Pipeline::new(block_registry)),
+        name => panic!(
+            "Unknown world generator {}, expected one of {}",
+            name,
+            GENERATORS.join(", ")
+        ),
+    }
+}
+
+/// The generator of the default worlds: biome-dependent terrain with caves, ores, trees and
+/// structures. The surface blocks come last so that they also cover the ground around the
+/// features.
+/// The terrain values and the caves of up to `column_cache` columns of chunks are cached.
+fn default_generator(
+    seed: u64,
+    settings: DensitySettings,
+    column_cache: usize,
+    block_registry: &BlockRegistry,
+) -> Pipeline {
+    let shape = Arc::new(TerrainShape::new(seed, settings, column_cache));
+    let ores = load_ore_definitions(Path::new("assets/ores.toml"));
+    let structures = load_structure_definitions(Path::new("assets/structures.toml"));
+    let mut pipeline = Pipeline::new(block_registry);
+    pipeline.set_terrain(shape.clone());
+    let carver = Arc::new(WormCarver::new(seed, column_cache, block_registry));
+    pipeline.add_stage(TerrainStage::new(shape.clone(), block_registry));
+    pipeline.add_stage(carver.clone());
+    pipeline.add_stage(OreStage::new(seed, &ores, block_registry));
+    pipeline.add_stage(TreeFeature::new(
+        seed,
+        shape.clone(),
+        carver.clone(),
+        block_registry,
+    ));
+    pipeline.add_stage(StructureStage::new(
+        seed,
+        shape.clone(),
+        carver,
+        &structures,
+        block_registry,
+    ));
+    pipeline.add_stage(SurfaceStage::new(shape, block_registry));
+    pipeline
+}
+
+/// Get the id of a block that worldgen needs
+pub fn get_block_id(registry: &BlockRegistry, name: &str) -> BlockId {
+    registry
+        .get_id(name)
+        .unwrap_or_else(|| panic!("Worldgen needs a block named {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{create_headless_block_registry, load_block_definitions};
+
+    /// Default density settings
+    pub const SETTINGS: DensitySettings = DensitySettings {
+        octaves: 4,
+        warp: 16.0,
+    };
+
+    fn generator_with_cache(seed: u64, column_cache: usize) -> Pipeline {
+        let definitions = load_block_definitions(Path::new("assets/blocks.toml"));
+        default_generator(
+            seed,
+            SETTINGS,
+            column_cache,
+            &create_headless_block_registry(&definitions),
+        )
+    }
+
+    fn generator(seed: u64) -> Pipeline {
+        generator_with_cache(seed, 64)
+    }
+
+    fn positions() -> Vec<ChunkPos> {
+        let mut positions = Vec::new();
+        for &x in &[-3, 0, 2] {
+            for &y in &[-2, -1, 0, 1] {
+                for &z in &[-1, 0, 5] {
+                    positions.push(ChunkPos([x, y, z]));
+                }
+            }
+        }
+        positions
+    }
+
+    #[test]
+    fn same_seed_same_chunks() {
+        let mut first = generator(1234);
+        // Another generator, generating the chunks in another order
+        let mut second = generator(1234);
+        let chunks: Vec<_> = positions()
+            .into_iter()
+            .map(|pos| first.generate(pos).to_array())
+            .collect();
+        for (pos, chunk) in positions().into_iter().zip(chunks).rev() {
+            assert!(
+                second.generate(pos).to_array()[..] == chunk[..],
+                "chunk {:?} differs",
+                pos
+            );
+        }
+    }
+
+    #[test]
+    fn column_cache_same_chunks() {
+        // A cache too small to hold every column
+        let mut cached = generator_with_cache(5, 2);
+        let mut uncached = generator_with_cache(5, 0);
+        for pos in positions() {
+            assert!(
+                cached.generate(pos).to_array()[..] == uncached.generate(pos).to_array()[..],
+                "chunk {:?} differs",
+                pos
+            );
+        }
+    }
+
+    #[test]
+    fn different_seeds_different_chunks() {
+        let mut first = generator(1);
+        let mut second = generator(2);
+        assert!(positions()
+            .into_iter()
+            .any(|pos| first.generate(pos).to_array()[..] != second.generate(pos).to_array()[..]));
+    }
+
+    /// FNV-1a hash of the blocks of a chunk. Unlike `DefaultHasher`, it never changes.
+    fn chunk_hash(chunk: &ChunkArray) -> u64 {
+        let mut hash = 0xCBF2_9CE4_8422_2325;
+        for block in chunk.iter().flatten().flatten() {
+            for &byte in &block.0.to_le_bytes() {
+                hash ^= u64::from(byte);
+                hash = u64::wrapping_mul(hash, 0x0100_0000_01B3);
+            }
+        }
+        hash
+    }
+
+    #[test]
+    fn random_stages_are_stable() {
+        // The caves and the ores only depend on the seed and on `WorldgenRng`, so they must stay
+        // the same on every platform and with every version
+        let definitions = load_block_definitions(Path::new("assets/blocks.toml"));
+        let registry = create_headless_block_registry(&definitions);
+        let stone = get_block_id(&registry, "stone");
+        let ores = load_ore_definitions(Path::new("assets/ores.toml"));
+        let pos = ChunkPos([2, -3, 5]);
+        let mut chunk = [[[stone; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
+        WormCarver::new(1234, 0, &registry).apply(&mut chunk, pos);
+        OreStage::new(1234, &ores, &registry).apply(&mut chunk, pos);
+        assert_eq!(chunk_hash(&chunk), 0x6768_dde5_e122_64f0);
+    }
+
+    #[test]
+    fn closest_chunks_first() {
+        let mut queue = ChunkQueue::new();
+        queue.player_chunks = vec![ChunkPos([0, 0, 0]), ChunkPos([10, 0, 0])];
+        for &x in &[5, 0, 3, 9] {
+            queue.pending.insert(ChunkPos([x, 0, 0]));
+        }
+        queue.pending.remove(&ChunkPos([3, 0, 0]));
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop_closest())
+            .map(|pos| pos.0[0])
+            .collect();
+        assert_eq!(order, vec![0, 9, 5]);
+    }
+}
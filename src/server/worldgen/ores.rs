@@ -0,0 +1,221 @@
+//! The stage placing ore veins, as declared in `assets/ores.toml`.
+
+use super::{get_block_id, Stage, WorldgenRng};
+use crate::block::{BlockId, BlockPos, BlockRegistry, ChunkArray, ChunkPos};
+use crate::util::mix_seed;
+use crate::CHUNK_SIZE;
+
+use rand::{Rng, SeedableRng};
+use serde_derive::Deserialize;
+use std::path::Path;
+
+/// An ore, as declared in `assets/ores.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct OreDefinition {
+    /// Name of the ore block
+    pub block: String,
+    /// Name of the block the ore replaces
+    pub host: String,
+    /// Veins start between these heights
+    pub min_height: i64,
+    pub max_height: i64,
+    /// Number of steps of the random walk of a vein
+    pub vein_size: usize,
+    /// Average number of veins per chunk
+    pub frequency: f64,
+}
+
+/// Layout of `assets/ores.toml`
+#[derive(Deserialize)]
+struct OreDefinitions {
+    ore: Vec<OreDefinition>,
+}
+
+pub fn load_ore_definitions(path: &Path) -> Vec<OreDefinition> {
+    let buf = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+    let definitions: OreDefinitions = toml::from_str(&buf)
+        .unwrap_or_else(|e| panic!("Failed to parse {}: {}", path.display(), e));
+    for ore in &definitions.ore {
+        // Only the veins of adjacent chunks are placed in a chunk
+        if ore.vein_size > CHUNK_SIZE {
+            panic!("{}: veins of {} are too large", path.display(), ore.block);
+        }
+        if ore.min_height >= ore.max_height {
+            panic!("{}: empty height range for {}", path.display(), ore.block);
+        }
+    }
+    definitions.ore
+}
+
+/// An ore, with the ids of its blocks
+struct Ore {
+    block: BlockId,
+    host: BlockId,
+    definition: OreDefinition,
+}
+
+/// Places the veins of every ore
+pub struct OreStage {
+    seed: u64,
+    ores: Vec<Ore>,
+}
+
+impl OreStage {
+    pub fn new(seed: u64, ores: &[OreDefinition], block_registry: &BlockRegistry) -> Self {
+        let ores = ores
+            .iter()
+            .map(|definition| Ore {
+                block: get_block_id(block_registry, &definition.block),
+                host: get_block_id(block_registry, &definition.host),
+                definition: definition.clone(),
+            })
+            .collect();
+        Self { seed, ores }
+    }
+
+    /// Place the blocks of the veins of an ore that start in `start_chunk` and are inside the
+    /// chunk at `pos`
+    fn place_veins(
+        &self,
+        chunk: &mut ChunkArray,
+        pos: ChunkPos,
+        ore: usize,
+        start_chunk: ChunkPos,
+    ) {
+        let Ore {
+            block,
+            host,
+            ref definition,
+        } = self.ores[ore];
+        let chunk_min = start_chunk.0[1] * CHUNK_SIZE as i64;
+        if chunk_min >= definition.max_height
+            || chunk_min + CHUNK_SIZE as i64 <= definition.min_height
+        {
+            return;
+        }
+
+        let mut rng = WorldgenRng::seed_from_u64(mix_seed(
+            self.seed,
+            &[
+                start_chunk.0[0],
+                start_chunk.0[1],
+                start_chunk.0[2],
+                ore as i64,
+            ],
+        ));
+        let mut veins = definition.frequency.floor() as usize;
+        if rng.gen::<f64>() < definition.frequency.fract() {
+            veins += 1;
+        }
+        for _ in 0..veins {
+            let mut block_pos = [0; 3];
+            for i in 0..3 {
+                block_pos[i] =
+                    start_chunk.0[i] * CHUNK_SIZE as i64 + rng.gen_range(0, CHUNK_SIZE as i64);
+            }
+            // Always walk so that the next veins don't depend on this one
+            let in_range =
+                definition.min_height <= block_pos[1] && block_pos[1] < definition.max_height;
+            for _ in 0..definition.vein_size {
+                let p = BlockPos(block_pos);
+                if in_range && p.chunk_pos() == pos {
+                    let [x, y, z] = p.pos_in_chunk();
+                    if chunk[x][y][z] == host {
+                        chunk[x][y][z] = block;
+                    }
+                }
+                let axis = rng.gen_range(0, 3);
+                block_pos[axis] += if rng.gen() { 1 } else { -1 };
+            }
+        }
+    }
+}
+
+impl Stage for OreStage {
+    fn apply(&self, chunk: &mut ChunkArray, pos: ChunkPos) {
+        // Include the veins of the adjacent chunks that extend into this chunk
+        for ore in 0..self.ores.len() {
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let start_chunk = ChunkPos([pos.0[0] + dx, pos.0[1] + dy, pos.0[2] + dz]);
+                        self.place_veins(chunk, pos, ore, start_chunk);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::SETTINGS;
+    use super::super::{default_generator, WorldGenerator};
+    use super::*;
+    use crate::block::{create_headless_block_registry, load_block_definitions};
+
+    /// Count the blocks of every ore by depth over a sample region, and print the counts
+    #[test]
+    fn ore_distribution() {
+        const BAND: i64 = 32;
+        const MIN_CY: i64 = -17;
+        const MAX_CY: i64 = 2;
+        let definitions = load_block_definitions(Path::new("assets/blocks.toml"));
+        let block_registry = create_headless_block_registry(&definitions);
+        let mut generator = default_generator(42, SETTINGS, 64, &block_registry);
+        let ores = load_ore_definitions(Path::new("assets/ores.toml"));
+        let ore_blocks: Vec<_> = ores
+            .iter()
+            .map(|ore| get_block_id(&block_registry, &ore.block))
+            .collect();
+        let bands = ((MAX_CY - MIN_CY) * CHUNK_SIZE as i64 / BAND) as usize;
+        let mut counts = vec![vec![0usize; bands]; ores.len()];
+
+        for cx in 0..2 {
+            for cy in MIN_CY..MAX_CY {
+                for cz in 0..2 {
+                    let chunk = generator.generate(ChunkPos([cx, cy, cz])).to_array();
+                    for x in 0..CHUNK_SIZE {
+                        for y in 0..CHUNK_SIZE {
+                            for z in 0..CHUNK_SIZE {
+                                let height = cy * CHUNK_SIZE as i64 + y as i64;
+                                for (i, ore) in ores.iter().enumerate() {
+                                    if ore_blocks[i] == chunk[x][y][z] {
+                                        let band = (height - MIN_CY * CHUNK_SIZE as i64) / BAND;
+                                        counts[i][band as usize] += 1;
+                                        let slack = ore.vein_size as i64;
+                                        assert!(
+                                            ore.min_height - slack <= height
+                                                && height < ore.max_height + slack,
+                                            "{} at height {}",
+                                            ore.block,
+                                            height
+                                        );
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        print!("{:>12}", "depth");
+        for ore in &ores {
+            print!("{:>12}", ore.block);
+        }
+        println!();
+        for band in (0..bands).rev() {
+            print!("{:>12}", MIN_CY * CHUNK_SIZE as i64 + band as i64 * BAND);
+            for ore_counts in &counts {
+                print!("{:>12}", ore_counts[band]);
+            }
+            println!();
+        }
+
+        for (ore, ore_counts) in ores.iter().zip(counts.iter()) {
+            assert!(ore_counts.iter().sum::<usize>() > 0, "no {}", ore.block);
+        }
+    }
+}
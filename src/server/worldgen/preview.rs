@@ -0,0 +1,173 @@
+//! Top-down images of generated terrain, used to compare seeds and generator changes without
+//! starting the client.
+
+use super::WorldGenerator;
+use crate::block::{BlockDefinition, BlockId, BlockPos, ChunkPos};
+use crate::CHUNK_SIZE;
+
+use image::{Rgb, RgbImage};
+
+/// What the pixels of a preview show
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PreviewMode {
+    /// Colour of the highest block of every column
+    Blocks,
+    /// Height of the highest block of every column, shaded so that slopes stand out
+    Height,
+    /// Horizontal slice at the given height, where air is black
+    Caves(i64),
+}
+
+/// Rectangle of columns of chunks covered by a preview
+#[derive(Debug, Clone, Copy)]
+pub struct PreviewArea {
+    /// Chunk coordinates of the corner of the rectangle with the lowest x and z
+    pub x: i64,
+    pub z: i64,
+    /// Size of the rectangle, in chunks
+    pub width: i64,
+    pub depth: i64,
+    /// Vertical range of the chunks that are searched for the highest blocks, in chunks
+    pub min_y: i64,
+    pub max_y: i64,
+}
+
+/// Average colour of the top texture of every block, indexed by block id. Blocks without
+/// textures are black.
+pub fn block_colors(definitions: &[BlockDefinition]) -> Vec<[u8; 3]> {
+    definitions
+        .iter()
+        .map(|definition| {
+            let top = definition
+                .textures
+                .as_ref()
+                .and_then(|textures| textures.faces()[4]);
+            match top {
+                Some(texture) => average_color(texture),
+                None => [0, 0, 0],
+            }
+        })
+        .collect()
+}
+
+/// Average colour of the opaque pixels of a texture
+fn average_color(texture: &str) -> [u8; 3] {
+    let path = format!("assets/{}.png", texture);
+    let image = image::open(&path)
+        .unwrap_or_else(|e| panic!("Failed to load texture {}: {}", path, e))
+        .to_rgba();
+    let mut total = [0u64; 3];
+    let mut count = 0;
+    for pixel in image.pixels().filter(|pixel| pixel[3] > 0) {
+        for i in 0..3 {
+            total[i] += u64::from(pixel[i]);
+        }
+        count += 1;
+    }
+    let mut color = [0; 3];
+    for i in 0..3 {
+        color[i] = (total[i] / u64::max(count, 1)) as u8;
+    }
+    color
+}
+
+/// Generate the chunks of an area and draw them. Every pixel is a column of blocks, with x
+/// going right and z going down.
+pub fn render_preview(
+    generator: &mut dyn WorldGenerator,
+    colors: &[[u8; 3]],
+    area: PreviewArea,
+    mode: PreviewMode,
+) -> RgbImage {
+    let size = CHUNK_SIZE as i64;
+    let (width, depth) = ((area.width * size) as u32, (area.depth * size) as u32);
+    let mut image = RgbImage::new(width, depth);
+    let color = |block: BlockId| Rgb(colors[block.0 as usize]);
+
+    match mode {
+        PreviewMode::Caves(y) => {
+            let pos = BlockPos([0, y, 0]);
+            let (cy, k) = (pos.chunk_pos().0[1], pos.pos_in_chunk()[1]);
+            for cx in 0..area.width {
+                for cz in 0..area.depth {
+                    let chunk = generator.generate(ChunkPos([area.x + cx, cy, area.z + cz]));
+                    for i in 0..CHUNK_SIZE {
+                        for j in 0..CHUNK_SIZE {
+                            let px = (cx * size) as u32 + i as u32;
+                            let pz = (cz * size) as u32 + j as u32;
+                            image.put_pixel(px, pz, color(chunk.get(i, k, j)));
+                        }
+                    }
+                }
+            }
+        }
+        PreviewMode::Blocks | PreviewMode::Height => {
+            let tops = highest_blocks(generator, area);
+            let top = |px: u32, pz: u32| tops[(pz * width + px) as usize];
+            for pz in 0..depth {
+                for px in 0..width {
+                    let pixel = match (mode, top(px, pz)) {
+                        (_, None) => Rgb([0, 0, 0]),
+                        (PreviewMode::Blocks, Some((_, block))) => color(block),
+                        (_, Some((height, _))) => {
+                            // Brighter on higher columns and on slopes facing the top left
+                            let (min, max) = (area.min_y * size, area.max_y * size);
+                            let mut shade = (height - min) as f64 / (max - min) as f64 * 255.0;
+                            if px > 0 && pz > 0 {
+                                if let Some((neighbour, _)) = top(px - 1, pz - 1) {
+                                    shade += 8.0 * (height - neighbour) as f64;
+                                }
+                            }
+                            let shade = f64::max(0.0, f64::min(255.0, shade)) as u8;
+                            Rgb([shade, shade, shade])
+                        }
+                    };
+                    image.put_pixel(px, pz, pixel);
+                }
+            }
+        }
+    }
+    image
+}
+
+/// Height and block of the highest block that is not air of every column of an area, indexed by
+/// `z * width + x`. `None` if the column only contains air.
+fn highest_blocks(
+    generator: &mut dyn WorldGenerator,
+    area: PreviewArea,
+) -> Vec<Option<(i64, BlockId)>> {
+    let size = CHUNK_SIZE as i64;
+    let width = (area.width * size) as usize;
+    let air = BlockId::from(0);
+    let mut tops = vec![None; width * (area.depth * size) as usize];
+    for cx in 0..area.width {
+        for cz in 0..area.depth {
+            let index = |i: usize, j: usize| {
+                (cz * size) as usize * width + j * width + (cx * size) as usize + i
+            };
+            // Generate the chunks from the top, until every column has a block
+            let mut missing = CHUNK_SIZE * CHUNK_SIZE;
+            let mut cy = area.max_y;
+            while missing > 0 && cy > area.min_y {
+                cy -= 1;
+                let chunk = generator.generate(ChunkPos([area.x + cx, cy, area.z + cz]));
+                for i in 0..CHUNK_SIZE {
+                    for j in 0..CHUNK_SIZE {
+                        if tops[index(i, j)].is_some() {
+                            continue;
+                        }
+                        for k in (0..CHUNK_SIZE).rev() {
+                            let block = chunk.get(i, k, j);
+                            if block != air {
+                                tops[index(i, j)] = Some((cy * size + k as i64, block));
+                                missing -= 1;
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    tops
+}
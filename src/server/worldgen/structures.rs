@@ -0,0 +1,140 @@
+//! The stage placing structures, as declared in `assets/structures.toml`.
+
+use super::carvers::WormCarver;
+use super::terrain::TerrainShape;
+use super::{Stage, WorldgenRng};
+use crate::block::{BlockPos, BlockRegistry, ChunkArray, ChunkPos};
+use crate::schematic::{PasteOptions, Schematic};
+use crate::util::mix_seed;
+use crate::CHUNK_SIZE;
+
+use rand::{Rng, SeedableRng};
+use serde_derive::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Mixed with the world seed so that structures don't appear next to the trees, which use the
+/// same coordinates
+const STRUCTURE_SEED: i64 = 0x5354_5255;
+
+/// A structure, as declared in `assets/structures.toml`
+#[derive(Debug, Clone, Deserialize)]
+pub struct StructureDefinition {
+    /// Path of the schematic, relative to `assets`
+    pub schematic: String,
+    /// Average number of structures per column of chunks
+    pub frequency: f64,
+}
+
+/// Layout of `assets/structures.toml`
+#[derive(Deserialize)]
+struct StructureDefinitions {
+    structure: Vec<StructureDefinition>,
+}
+
+pub fn load_structure_definitions(path: &Path) -> Vec<StructureDefinition> {
+    let buf = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+    let definitions: StructureDefinitions = toml::from_str(&buf)
+        .unwrap_or_else(|e| panic!("Failed to parse {}: {}", path.display(), e));
+    definitions.structure
+}
+
+/// Places schematics on the ground, with their origin right above the highest block left by the
+/// caves and a random rotation and mirroring. The air of the schematics doesn't replace blocks.
+pub struct StructureStage {
+    seed: u64,
+    shape: Arc<TerrainShape>,
+    carver: Arc<WormCarver>,
+    structures: Vec<(Schematic, f64)>,
+    /// Structures can't reach columns of chunks that are further than this from the column they
+    /// start in
+    range: i64,
+}
+
+impl StructureStage {
+    pub fn new(
+        seed: u64,
+        shape: Arc<TerrainShape>,
+        carver: Arc<WormCarver>,
+        definitions: &[StructureDefinition],
+        block_registry: &BlockRegistry,
+    ) -> Self {
+        let structures: Vec<_> = definitions
+            .iter()
+            .map(|definition| {
+                let path = Path::new("assets").join(&definition.schematic);
+                let schematic = Schematic::load(&path, block_registry).unwrap_or_else(|e| {
+                    panic!("Failed to load schematic {}: {}", path.display(), e)
+                });
+                (schematic, definition.frequency)
+            })
+            .collect();
+        // Rotations swap the x and z axes
+        let reach = structures
+            .iter()
+            .map(|(schematic, _)| {
+                let size = schematic.blocks.size();
+                [0, 2]
+                    .iter()
+                    .map(|&i| {
+                        i64::max(
+                            schematic.origin[i],
+                            size[i] as i64 - 1 - schematic.origin[i],
+                        )
+                    })
+                    .max()
+                    .unwrap()
+            })
+            .max()
+            .unwrap_or(0);
+        Self {
+            seed: mix_seed(seed, &[STRUCTURE_SEED]),
+            shape,
+            carver,
+            structures,
+            range: (reach + CHUNK_SIZE as i64 - 1) / CHUNK_SIZE as i64,
+        }
+    }
+
+    /// Schematic, origin and options of the structures starting in a column of chunks
+    fn placements(&self, cx: i64, cz: i64) -> Vec<(usize, BlockPos, PasteOptions)> {
+        let mut placements = Vec::new();
+        for (index, &(_, frequency)) in self.structures.iter().enumerate() {
+            let mut rng = WorldgenRng::seed_from_u64(mix_seed(self.seed, &[cx, cz, index as i64]));
+            let mut count = frequency.floor() as usize;
+            if rng.gen::<f64>() < frequency.fract() {
+                count += 1;
+            }
+            for _ in 0..count {
+                let x = cx * CHUNK_SIZE as i64 + rng.gen_range(0, CHUNK_SIZE as i64);
+                let z = cz * CHUNK_SIZE as i64 + rng.gen_range(0, CHUNK_SIZE as i64);
+                let options = PasteOptions {
+                    rotation: rng.gen_range(0, 4),
+                    mirror: rng.gen(),
+                    skip_air: true,
+                };
+                let y = self.carver.top_solid_block(&self.shape, x, z) + 1;
+                let origin = BlockPos([x, y, z]);
+                placements.push((index, origin, options));
+            }
+        }
+        placements
+    }
+}
+
+impl Stage for StructureStage {
+    fn apply(&self, chunk: &mut ChunkArray, pos: ChunkPos) {
+        // Columns are visited in the same order by every chunk, so that overlapping structures
+        // give the same blocks on both sides of a chunk border
+        for dx in -self.range..=self.range {
+            for dz in -self.range..=self.range {
+                for (index, origin, options) in self.placements(pos.0[0] + dx, pos.0[2] + dz) {
+                    self.structures[index]
+                        .0
+                        .paste_into_array(chunk, pos, origin, options);
+                }
+            }
+        }
+    }
+}
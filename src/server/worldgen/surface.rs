@@ -0,0 +1,76 @@
+//! The stage covering the terrain with the surface blocks of its biomes.
+
+use super::terrain::TerrainShape;
+use super::{get_block_id, Stage};
+use crate::biome::BIOMES;
+use crate::block::{BlockId, BlockRegistry, ChunkArray, ChunkPos};
+use crate::CHUNK_SIZE;
+
+use std::sync::Arc;
+
+/// Depth of the subsurface blocks
+const SUBSURFACE_DEPTH: usize = 5;
+
+/// Replaces the stone blocks of the terrain that are right under air by the surface and
+/// subsurface blocks of the biome of each column. This includes the top of overhangs. The stone
+/// of the features above the terrain is kept.
+pub struct SurfaceStage {
+    shape: Arc<TerrainShape>,
+    stone: BlockId,
+    /// Surface block of every biome of `BIOMES`
+    surface: Vec<BlockId>,
+    /// Subsurface block of every biome of `BIOMES`
+    subsurface: Vec<BlockId>,
+}
+
+impl SurfaceStage {
+    pub fn new(shape: Arc<TerrainShape>, block_registry: &BlockRegistry) -> Self {
+        let get = |name| get_block_id(block_registry, name);
+        Self {
+            shape,
+            stone: get("stone"),
+            surface: BIOMES
+                .iter()
+                .map(|biome| get(biome.properties().surface_block))
+                .collect(),
+            subsurface: BIOMES
+                .iter()
+                .map(|biome| get(biome.properties().subsurface_block))
+                .collect(),
+        }
+    }
+}
+
+impl Stage for SurfaceStage {
+    fn apply(&self, chunk: &mut ChunkArray, pos: ChunkPos) {
+        let (cx, cy, cz) = (pos.0[0], pos.0[1], pos.0[2]);
+        let min_y = cy * CHUNK_SIZE as i64;
+        for i in 0..CHUNK_SIZE {
+            for j in 0..CHUNK_SIZE {
+                let (x, z) = (
+                    CHUNK_SIZE as i64 * cx + i as i64,
+                    CHUNK_SIZE as i64 * cz + j as i64,
+                );
+                // Include the blocks above the chunk that can cover its blocks
+                let max_y = min_y + (CHUNK_SIZE + SUBSURFACE_DEPTH + 1) as i64;
+                let solid = self.shape.solid_column(x, z, min_y, max_y);
+                let biome = self.shape.biome(x, z) as usize;
+
+                // Number of solid blocks from the current block to the closest air block above
+                // it. The blocks above the column are assumed to be solid.
+                let mut depth = SUBSURFACE_DEPTH + 1;
+                for (k, &solid) in solid.iter().enumerate().rev() {
+                    depth = if solid { depth + 1 } else { 0 };
+                    if k >= CHUNK_SIZE || !solid || chunk[i][k][j] != self.stone {
+                        continue;
+                    }
+                    if depth == 1 {
+                        chunk[i][k][j] = self.surface[biome];
+                    } else if depth <= SUBSURFACE_DEPTH + 1 {
+                        chunk[i][k][j] = self.subsurface[biome];
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Shape of the terrain, and the stage filling it with stone.
+//! The terrain is the set of blocks where a 3D density function is positive. The density is the
+//! distance to the height of the terrain given by the biomes, moved by some fractal noise so that
+//! cliffs and overhangs can form. The noise is sampled at warped positions to avoid the regular
+//! look of raw Perlin noise.
+
+use super::{get_block_id, Stage};
+use crate::biome::{Biome, BiomeSource, ChunkBiomes, BIOMES};
+use crate::block::{BlockId, BlockPos, BlockRegistry, ChunkArray, ChunkPos};
+use crate::util::{mix_seed, LruCache};
+use crate::CHUNK_SIZE;
+
+use noise::{NoiseFn, Perlin, Seedable};
+use std::sync::{Arc, Mutex};
+
+/// Scale of the first octave of the density noise
+const DENSITY_SCALE: f64 = 1.0 / 64.0;
+/// Scale of the domain warping noise
+const WARP_SCALE: f64 = 1.0 / 128.0;
+
+/// Parameters of the density noise
+#[derive(Debug, Clone, Copy)]
+pub struct DensitySettings {
+    /// Number of octaves of the density noise. Each octave has twice the frequency and half the
+    /// amplitude of the previous one.
+    pub octaves: usize,
+    /// How far, in blocks, the density noise is sampled from the actual position
+    pub warp: f64,
+}
+
+/// Height, overhang and biome of every column of blocks of a column of chunks
+struct ChunkColumn {
+    /// Result of `TerrainShape::compute_column`, indexed by `[x][z]`
+    columns: [[(f64, f64); CHUNK_SIZE]; CHUNK_SIZE],
+    biomes: ChunkBiomes,
+}
+
+/// Height, density and biome of every column of a world, shared by the stages that follow the
+/// terrain. The 2D values are computed once for a whole column of chunks, and cached.
+pub struct TerrainShape {
+    perlin: Perlin,
+    density: Perlin,
+    warp: [Perlin; 3],
+    settings: DensitySettings,
+    biomes: BiomeSource,
+    /// `None` if the cache is disabled
+    cache: Option<Mutex<LruCache<(i64, i64), Arc<ChunkColumn>>>>,
+}
+
+impl TerrainShape {
+    /// Create the shape of the terrain, caching the values of up to `cache_size` columns of
+    /// chunks
+    pub fn new(seed: u64, settings: DensitySettings, cache_size: usize) -> Self {
+        let perlin = |i| Perlin::new().set_seed(mix_seed(seed, &[i]) as u32);
+        Self {
+            perlin: perlin(0),
+            density: perlin(3),
+            warp: [perlin(4), perlin(5), perlin(6)],
+            settings: DensitySettings {
+                octaves: settings.octaves.max(1),
+                ..settings
+            },
+            biomes: BiomeSource::new(seed),
+            cache: if cache_size > 0 {
+                Some(Mutex::new(LruCache::new(cache_size)))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Get the values of the column of chunks containing the column of blocks (x, z), and the
+    /// position of (x, z) in it. Returns `None` if the cache is disabled.
+    fn chunk_column(&self, x: i64, z: i64) -> Option<(Arc<ChunkColumn>, usize, usize)> {
+        let cache = self.cache.as_ref()?;
+        let size = CHUNK_SIZE as i64;
+        let pos = BlockPos([x, 0, z]);
+        let ChunkPos([cx, _, cz]) = pos.chunk_pos();
+        let [i, _, j] = pos.pos_in_chunk();
+        if let Some(column) = cache.lock().unwrap().get(&(cx, cz)) {
+            return Some((column, i, j));
+        }
+
+        let mut column = ChunkColumn {
+            columns: [[(0.0, 0.0); CHUNK_SIZE]; CHUNK_SIZE],
+            biomes: [[Biome::Plains; CHUNK_SIZE]; CHUNK_SIZE],
+        };
+        for ci in 0..CHUNK_SIZE {
+            for cj in 0..CHUNK_SIZE {
+                let (bx, bz) = (cx * size + ci as i64, cz * size + cj as i64);
+                column.columns[ci][cj] = self.compute_column(bx, bz);
+                column.biomes[ci][cj] = self.biomes.biome(bx, bz);
+            }
+        }
+        let column = Arc::new(column);
+        cache.lock().unwrap().insert((cx, cz), column.clone());
+        Some((column, i, j))
+    }
+
+    /// Height of the terrain in a column of blocks before the density noise is applied, and
+    /// how far the noise can move the terrain from this height
+    fn column(&self, x: i64, z: i64) -> (f64, f64) {
+        match self.chunk_column(x, z) {
+            Some((column, i, j)) => column.columns[i][j],
+            None => self.compute_column(x, z),
+        }
+    }
+
+    /// Compute the result of `column`. The height curves of the biomes are blended near biome
+    /// borders.
+    fn compute_column(&self, x: i64, z: i64) -> (f64, f64) {
+        let noise = self.perlin.get([
+            0.005 * (0.0021 + x as f64 / 3.0),
+            0.5,
+            0.005 * (0.0021 + z as f64 / 3.0),
+        ]);
+        let weights = self.biomes.weights(x, z);
+        let mut height = 0.0;
+        let mut overhang = 0.0;
+        for (weight, biome) in weights.iter().zip(BIOMES.iter()) {
+            let properties = biome.properties();
+            height += weight * (properties.base_height + properties.height_variation * noise);
+            overhang += weight * properties.overhang;
+        }
+        (height, overhang)
+    }
+
+    /// Fractal noise at a warped position, between -1 and 1
+    fn noise(&self, x: i64, y: i64, z: i64) -> f64 {
+        let point = [x as f64, y as f64, z as f64];
+        let warp_point = [
+            point[0] * WARP_SCALE,
+            point[1] * WARP_SCALE,
+            point[2] * WARP_SCALE,
+        ];
+        let mut warped = point;
+        for i in 0..3 {
+            warped[i] += self.settings.warp * self.warp[i].get(warp_point);
+        }
+
+        let (mut total, mut norm) = (0.0, 0.0);
+        let (mut frequency, mut amplitude) = (DENSITY_SCALE, 1.0);
+        for _ in 0..self.settings.octaves {
+            total += amplitude
+                * self.density.get([
+                    warped[0] * frequency,
+                    warped[1] * frequency,
+                    warped[2] * frequency,
+                ]);
+            norm += amplitude;
+            frequency *= 2.0;
+            amplitude /= 2.0;
+        }
+        f64::max(-1.0, f64::min(1.0, total / norm))
+    }
+
+    /// Is the block at (x, y, z) part of the terrain ? `column` must be the result of
+    /// `self.column(x, z)`.
+    fn is_solid_in_column(&self, column: (f64, f64), x: i64, y: i64, z: i64) -> bool {
+        let (height, overhang) = column;
+        let distance = height - y as f64;
+        // The noise is between -1 and 1, so it can't change the sign of the density
+        if distance > overhang {
+            true
+        } else if distance < -overhang {
+            false
+        } else {
+            distance + overhang * self.noise(x, y, z) > 0.0
+        }
+    }
+
+    /// Whether every block of a column, from `min_y` (included) to `max_y` (excluded), is part of
+    /// the terrain
+    pub fn solid_column(&self, x: i64, z: i64, min_y: i64, max_y: i64) -> Vec<bool> {
+        let column = self.column(x, z);
+        (min_y..max_y)
+            .map(|y| self.is_solid_in_column(column, x, y, z))
+            .collect()
+    }
+
+    /// Height of the highest block of the terrain in a column
+    pub fn surface_height(&self, x: i64, z: i64) -> i64 {
+        let column = self.column(x, z);
+        let (height, overhang) = column;
+        let mut y = (height + overhang).ceil() as i64;
+        while !self.is_solid_in_column(column, x, y, z) {
+            y -= 1;
+        }
+        y
+    }
+
+    pub fn biome(&self, x: i64, z: i64) -> Biome {
+        match self.chunk_column(x, z) {
+            Some((column, i, j)) => column.biomes[i][j],
+            None => self.biomes.biome(x, z),
+        }
+    }
+}
+
+/// Fills the terrain with stone
+pub struct TerrainStage {
+    shape: Arc<TerrainShape>,
+    stone: BlockId,
+}
+
+impl TerrainStage {
+    pub fn new(shape: Arc<TerrainShape>, block_registry: &BlockRegistry) -> Self {
+        Self {
+            shape,
+            stone: get_block_id(block_registry, "stone"),
+        }
+    }
+}
+
+impl Stage for TerrainStage {
+    fn apply(&self, chunk: &mut ChunkArray, pos: ChunkPos) {
+        let (cx, cy, cz) = (pos.0[0], pos.0[1], pos.0[2]);
+        let min_y = cy * CHUNK_SIZE as i64;
+        for i in 0..CHUNK_SIZE {
+            for j in 0..CHUNK_SIZE {
+                let solid = self.shape.solid_column(
+                    CHUNK_SIZE as i64 * cx + i as i64,
+                    CHUNK_SIZE as i64 * cz + j as i64,
+                    min_y,
+                    min_y + CHUNK_SIZE as i64,
+                );
+                for (k, &solid) in solid.iter().enumerate() {
+                    if solid {
+                        chunk[i][k][j] = self.stone;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -6,16 +6,19 @@ pub mod network {
 
     pub struct SimpleServer {
         from_client: Receiver<Vec<u8>>,
-        to_client: Sender<Vec<u8>>,
+        /// `None` once the server closed the connection
+        to_client: Option<Sender<Vec<u8>>>,
         sent_connected: bool,
+        sent_closed: bool,
     }
 
     impl SimpleServer {
         pub fn new(from_client: Receiver<Vec<u8>>, to_client: Sender<Vec<u8>>) -> Self {
             Self {
                 from_client,
-                to_client,
+                to_client: Some(to_client),
                 sent_connected: false,
+                sent_closed: false,
             }
         }
     }
@@ -26,6 +29,13 @@ pub mod network {
                 self.sent_connected = true;
                 return Some(ServerEvent::Connection(0));
             }
+            if self.to_client.is_none() {
+                if self.sent_closed {
+                    return None;
+                }
+                self.sent_closed = true;
+                return Some(ServerEvent::ConnectionClosed(0));
+            }
             match self.from_client.try_recv() {
                 Err(TryRecvError::Empty) => None,
                 Err(TryRecvError::Disconnected) => Some(ServerEvent::ConnectionClosed(0)),
@@ -35,11 +45,21 @@ pub mod network {
 
         fn send_message(&mut self, client: ConnectionId, message: Vec<u8>) {
             if client == 0 {
-                self.to_client.send(message).unwrap();
+                // The next event is `ConnectionClosed` if the client is gone
+                if let Some(ref to_client) = self.to_client {
+                    let _ = to_client.send(message);
+                }
             } else {
                 println!("WARNING: request to send message to client {}, but SimpleServer only handles client 0!", client);
             }
         }
+
+        fn disconnect(&mut self, client: ConnectionId) {
+            if client == 0 {
+                // The client notices that the channel is closed
+                self.to_client = None;
+            }
+        }
     }
 
     pub struct SimpleClient {
@@ -72,7 +92,8 @@ pub mod network {
         }
 
         fn send_message(&mut self, message: Vec<u8>) {
-            self.to_server.send(message).unwrap();
+            // The next event is `ConnectionClosed` if the server is gone
+            let _ = self.to_server.send(message);
         }
     }
 }
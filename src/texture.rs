@@ -1,6 +1,7 @@
 //! Textures-related data structures and helpers.
 
 use gfx;
+use image::RgbaImage;
 use std::collections::HashMap;
 use texture_packer::TexturePackerConfig;
 
@@ -28,16 +29,8 @@ const TEXTURE_PACKER_CONFIG: TexturePackerConfig = TexturePackerConfig {
     texture_outlines: false,
 };
 
-pub fn load_textures<F, R>(
-    factory: &mut F,
-) -> (
-    gfx::handle::ShaderResourceView<R, [f32; 4]>,
-    TextureRegistry,
-)
-where
-    F: gfx::Factory<R>,
-    R: gfx::Resources,
-{
+/// Pack the textures in an atlas, without uploading it to the GPU
+fn pack_textures(textures: &[String]) -> (RgbaImage, TextureRegistry) {
     use image::{GenericImage, ImageBuffer};
     use std::path::Path;
     use texture_packer::exporter::ImageExporter;
@@ -46,21 +39,12 @@ where
 
     let mut packer = TexturePacker::new_skyline(TEXTURE_PACKER_CONFIG);
     let mut registry = TextureRegistry::new();
-    let textures = [
-        "dirt",
-        "grass_side",
-        "grass_top",
-        "wood_side",
-        "wood_top",
-        "leaves",
-        "stone",
-        "ore_coal",
-    ];
-    for &tex in &textures {
+    for tex in textures {
         let path = format!("assets/{}.png", tex);
         packer.pack_own(
-            String::from(tex),
-            ImageImporter::import_from_file(&Path::new(&path)).unwrap(),
+            tex.clone(),
+            ImageImporter::import_from_file(&Path::new(&path))
+                .unwrap_or_else(|e| panic!("Failed to load texture {}: {}", path, e)),
         );
     }
     for (name, frame) in packer.get_frames() {
@@ -77,6 +61,22 @@ where
     }
     let mut buffer = ImageBuffer::new(MAX_TEXTURE_SIZE, MAX_TEXTURE_SIZE);
     buffer.copy_from(&ImageExporter::export(&packer).unwrap(), 0, 0);
+    (buffer, registry)
+}
+
+/// Load the textures from the `assets` directory and upload them to the GPU
+pub fn load_textures<F, R>(
+    factory: &mut F,
+    textures: &[String],
+) -> (
+    gfx::handle::ShaderResourceView<R, [f32; 4]>,
+    TextureRegistry,
+)
+where
+    F: gfx::Factory<R>,
+    R: gfx::Resources,
+{
+    let (buffer, registry) = pack_textures(textures);
     let kind = gfx::texture::Kind::D2(
         MAX_TEXTURE_SIZE as u16,
         MAX_TEXTURE_SIZE as u16,
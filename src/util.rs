@@ -1,5 +1,10 @@
 //! A few other helpers.
 
+use ::std::collections::HashMap;
+use ::std::fs::{self, File};
+use ::std::hash::Hash;
+use ::std::io::{self, Write};
+use ::std::path::Path;
 use ::std::time::{Duration, Instant};
 
 pub struct Ticker {
@@ -32,3 +37,163 @@ impl Ticker {
         }
     }
 }
+
+/// Mix a seed with some values, such as coordinates, using the SplitMix64 finalizer so that
+/// close values give unrelated seeds
+pub fn mix_seed(seed: u64, values: &[i64]) -> u64 {
+    let mut hash = seed;
+    for &value in values {
+        let mut z = (hash ^ value as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        hash = z ^ (z >> 31);
+    }
+    hash
+}
+
+/// Replace the contents of a file. The data is written to a temporary file next to it, which then
+/// replaces the file, so that a crash leaves either the old or the new contents.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+/// A map holding at most `capacity` values, which drops the least recently used value when it is
+/// full. The entries form a list ordered by last use, linked by their indices.
+pub struct LruCache<K, V> {
+    capacity: usize,
+    /// Index of every key in `entries`
+    indices: HashMap<K, usize>,
+    entries: Vec<LruEntry<K, V>>,
+    /// Most recently used entry
+    head: Option<usize>,
+    /// Least recently used entry, replaced when the cache is full
+    tail: Option<usize>,
+}
+
+struct LruEntry<K, V> {
+    key: K,
+    value: V,
+    /// Entry used more recently
+    prev: Option<usize>,
+    /// Entry used less recently
+    next: Option<usize>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            indices: HashMap::new(),
+            entries: Vec::new(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let index = *self.indices.get(key)?;
+        self.unlink(index);
+        self.push_front(index);
+        Some(self.entries[index].value.clone())
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let index = match self.indices.get(&key) {
+            Some(&index) => {
+                self.entries[index].value = value;
+                self.unlink(index);
+                index
+            }
+            None if self.entries.len() < self.capacity => {
+                self.entries.push(LruEntry {
+                    key: key.clone(),
+                    value,
+                    prev: None,
+                    next: None,
+                });
+                self.indices.insert(key, self.entries.len() - 1);
+                self.entries.len() - 1
+            }
+            None => {
+                // Reuse the entry of the least recently used value
+                let index = self.tail.unwrap();
+                self.unlink(index);
+                let entry = &mut self.entries[index];
+                self.indices.remove(&entry.key);
+                entry.key = key.clone();
+                entry.value = value;
+                self.indices.insert(key, index);
+                index
+            }
+        };
+        self.push_front(index);
+    }
+
+    /// Remove an entry from the list
+    fn unlink(&mut self, index: usize) {
+        let (prev, next) = (self.entries[index].prev, self.entries[index].next);
+        match prev {
+            Some(prev) => self.entries[prev].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.entries[next].prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    /// Insert an entry that is not in the list at the front of the list
+    fn push_front(&mut self, index: usize) {
+        self.entries[index].prev = None;
+        self.entries[index].next = self.head;
+        match self.head {
+            Some(head) => self.entries[head].prev = Some(index),
+            None => self.tail = Some(index),
+        }
+        self.head = Some(index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_cache() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, "a");
+        cache.insert(2, "b");
+        assert_eq!(cache.get(&1), Some("a"));
+        // 2 is the least recently used value
+        cache.insert(3, "c");
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some("a"));
+        assert_eq!(cache.get(&3), Some("c"));
+
+        // Inserting a value again updates it and makes it the most recently used one
+        cache.insert(1, "d");
+        cache.insert(4, "e");
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.get(&1), Some("d"));
+        assert_eq!(cache.get(&4), Some("e"));
+
+        let mut single = LruCache::new(1);
+        single.insert(1, "a");
+        single.insert(2, "b");
+        assert_eq!(single.get(&1), None);
+        assert_eq!(single.get(&2), Some("b"));
+
+        let mut disabled = LruCache::new(0);
+        disabled.insert(1, "a");
+        assert_eq!(disabled.get(&1), None);
+    }
+}
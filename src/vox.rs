@@ -0,0 +1,866 @@
+//! MagicaVoxel `.vox` files, used to model buildings outside of the game.
+//! A file holds models of at most 256³ voxels, placed in a scene by a graph of transform, group
+//! and shape nodes, and a palette of 255 colours. Voxels become blocks through a `ColorTable`.
+//! The vertical axis of MagicaVoxel is z, which becomes our y axis. Files whose scene graph
+//! rotates models are refused.
+
+use crate::block::{BlockId, BlockRegion, BlockRegistry};
+use crate::util::write_atomic;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Largest side of a model
+const MAX_MODEL_SIZE: usize = 256;
+/// Version written in the files we save
+const VOX_VERSION: i32 = 150;
+/// Deepest scene graph that is read, so that a long chain of nodes can't overflow the stack
+const MAX_NODE_DEPTH: usize = 64;
+/// Largest number of nodes visited while reading a scene graph, so that groups sharing their
+/// children can't make us visit the same nodes exponentially many times
+const MAX_VISITED_NODES: usize = 1 << 16;
+/// Largest number of voxels in the bounding box of a scene that is read, and of voxels placed in
+/// it, so that models placed far apart or many times can't make us allocate gigabytes
+const MAX_SCENE_VOLUME: u64 = 1 << 26;
+/// Value of the `_r` attribute of the transforms that don't rotate
+const IDENTITY_ROTATION: &str = "4";
+
+/// A model placed in a scene
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxModel {
+    /// Position of the lowest corner of the model in the scene
+    pub offset: [i32; 3],
+    pub size: [u32; 3],
+    /// Position and colour index of every voxel
+    pub voxels: Vec<[u8; 4]>,
+}
+
+/// The contents of a `.vox` file
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxScene {
+    pub models: Vec<VoxModel>,
+    /// RGBA colour of the 256 colour indices. Index 0 is empty space.
+    pub palette: Vec<[u8; 4]>,
+}
+
+/// The palette of MagicaVoxel, used by the files that don't have their own
+pub fn default_palette() -> Vec<[u8; 4]> {
+    let mut palette = vec![[0, 0, 0, 0]];
+    let levels = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    for &r in &levels {
+        for &g in &levels {
+            for &b in &levels {
+                if [r, g, b] != [0, 0, 0] {
+                    palette.push([r, g, b, 0xff]);
+                }
+            }
+        }
+    }
+    // Red, green, blue and grey ramps
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    for channel in 0..3 {
+        for &level in &ramp {
+            let mut color = [0, 0, 0, 0xff];
+            color[channel] = level;
+            palette.push(color);
+        }
+    }
+    for &level in &ramp {
+        palette.push([level, level, level, 0xff]);
+    }
+    palette
+}
+
+/// Conversion between the colours of the palettes and blocks, as declared in
+/// `assets/vox_colors.toml`
+pub struct ColorTable {
+    colors: Vec<([u8; 3], BlockId)>,
+    /// Name of every block, used in error messages
+    block_names: HashMap<BlockId, String>,
+}
+
+impl ColorTable {
+    pub fn new(colors: Vec<([u8; 3], BlockId)>, block_registry: &BlockRegistry) -> Self {
+        Self {
+            colors,
+            block_names: block_registry
+                .block_table()
+                .into_iter()
+                .map(|(name, id)| (id, name))
+                .collect(),
+        }
+    }
+
+    /// The block whose colour is the closest to `color`
+    pub fn block(&self, color: [u8; 3]) -> BlockId {
+        let distance = |other: [u8; 3]| {
+            (0..3)
+                .map(|i| {
+                    let d = i32::from(color[i]) - i32::from(other[i]);
+                    d * d
+                })
+                .sum::<i32>()
+        };
+        self.colors
+            .iter()
+            .min_by_key(|(other, _)| distance(*other))
+            .map(|&(_, block)| block)
+            .unwrap_or_else(|| BlockId::from(0))
+    }
+
+    /// The colour of a block, if it has one
+    pub fn color(&self, block: BlockId) -> Option<[u8; 3]> {
+        self.colors
+            .iter()
+            .find(|&&(_, other)| other == block)
+            .map(|&(color, _)| color)
+    }
+
+    fn block_name(&self, block: BlockId) -> String {
+        self.block_names
+            .get(&block)
+            .cloned()
+            .unwrap_or_else(|| format!("#{}", block.0))
+    }
+}
+
+/// Load the colour table, a map from block names to `rrggbb` colours
+pub fn load_color_table(path: &Path, block_registry: &BlockRegistry) -> ColorTable {
+    let buf = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+    let table: BTreeMap<String, String> = toml::from_str(&buf)
+        .unwrap_or_else(|e| panic!("Failed to parse {}: {}", path.display(), e));
+    let colors = table
+        .iter()
+        .map(|(name, color)| {
+            let block = block_registry
+                .get_id(name)
+                .unwrap_or_else(|| panic!("{}: unknown block {}", path.display(), name));
+            let color = parse_color(color).unwrap_or_else(|| {
+                panic!("{}: invalid colour {} for {}", path.display(), color, name)
+            });
+            (color, block)
+        })
+        .collect();
+    ColorTable::new(colors, block_registry)
+}
+
+/// Parse a `rrggbb` colour, with an optional leading `#`
+fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+pub fn load_vox(path: &Path) -> io::Result<VoxScene> {
+    read_vox(&fs::read(path)?)
+}
+
+pub fn save_vox(path: &Path, scene: &VoxScene) -> io::Result<()> {
+    write_atomic(path, &write_vox(scene))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the little-endian values of a file
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < length {
+            return Err(invalid("unexpected end of file"));
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let b = self.take(4)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn length(&mut self) -> io::Result<usize> {
+        match self.i32()? {
+            length if length >= 0 => Ok(length as usize),
+            _ => Err(invalid("negative length")),
+        }
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let length = self.length()?;
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| invalid("invalid string"))
+    }
+
+    fn dict(&mut self) -> io::Result<HashMap<String, String>> {
+        let mut dict = HashMap::new();
+        for _ in 0..self.length()? {
+            let key = self.string()?;
+            dict.insert(key, self.string()?);
+        }
+        Ok(dict)
+    }
+
+    /// Id, content and children of the next chunk
+    fn chunk(&mut self) -> io::Result<(&'a [u8], &'a [u8], &'a [u8])> {
+        let id = self.take(4)?;
+        let content_length = self.length()?;
+        let children_length = self.length()?;
+        Ok((id, self.take(content_length)?, self.take(children_length)?))
+    }
+}
+
+/// A node of the scene graph
+enum Node {
+    Transform { translation: [i32; 3], child: i32 },
+    Group(Vec<i32>),
+    Shape(Vec<i32>),
+}
+
+/// Parse the contents of a `.vox` file
+pub fn read_vox(bytes: &[u8]) -> io::Result<VoxScene> {
+    let mut reader = Reader { bytes };
+    if reader.take(4)? != b"VOX " {
+        return Err(invalid("not a .vox file"));
+    }
+    reader.i32()?;
+    let (id, _, children) = reader.chunk()?;
+    if id != b"MAIN" {
+        return Err(invalid("no MAIN chunk"));
+    }
+
+    // Models as (size, voxels), in the order of the file
+    let mut models = Vec::new();
+    let mut size = None;
+    let mut palette = None;
+    let mut nodes = HashMap::new();
+    let mut reader = Reader { bytes: children };
+    while !reader.bytes.is_empty() {
+        let (id, content, _) = reader.chunk()?;
+        let mut content = Reader { bytes: content };
+        match id {
+            b"SIZE" => {
+                let mut s = [0; 3];
+                for c in s.iter_mut() {
+                    *c = content.length()? as u32;
+                }
+                if s.iter().any(|&c| c as usize > MAX_MODEL_SIZE) {
+                    return Err(invalid("model larger than 256 voxels"));
+                }
+                size = Some(s);
+            }
+            b"XYZI" => {
+                let size = size
+                    .take()
+                    .ok_or_else(|| invalid("XYZI chunk without size"))?;
+                let mut voxels = Vec::new();
+                for _ in 0..content.length()? {
+                    let v = content.take(4)?;
+                    if (0..3).any(|i| u32::from(v[i]) >= size[i]) {
+                        return Err(invalid("voxel outside of its model"));
+                    }
+                    voxels.push([v[0], v[1], v[2], v[3]]);
+                }
+                models.push((size, voxels));
+            }
+            b"RGBA" => {
+                // The colour of index i is stored at i - 1
+                let mut colors = vec![[0, 0, 0, 0]];
+                for _ in 1..256 {
+                    let c = content.take(4)?;
+                    colors.push([c[0], c[1], c[2], c[3]]);
+                }
+                palette = Some(colors);
+            }
+            b"nTRN" => {
+                let id = content.i32()?;
+                content.dict()?;
+                let child = content.i32()?;
+                // Reserved id and layer
+                content.i32()?;
+                content.i32()?;
+                let mut translation = [0; 3];
+                if content.length()? > 0 {
+                    let frame = content.dict()?;
+                    if let Some(r) = frame.get("_r") {
+                        if r.trim() != IDENTITY_ROTATION {
+                            return Err(invalid("rotated models are not supported"));
+                        }
+                    }
+                    if let Some(t) = frame.get("_t") {
+                        let t: Vec<i32> = t
+                            .split_whitespace()
+                            .map(|c| c.parse().map_err(|_| invalid("invalid translation")))
+                            .collect::<io::Result<_>>()?;
+                        if t.len() != 3 {
+                            return Err(invalid("invalid translation"));
+                        }
+                        translation.copy_from_slice(&t);
+                    }
+                }
+                nodes.insert(id, Node::Transform { translation, child });
+            }
+            b"nGRP" => {
+                let id = content.i32()?;
+                content.dict()?;
+                let children = (0..content.length()?)
+                    .map(|_| content.i32())
+                    .collect::<io::Result<_>>()?;
+                nodes.insert(id, Node::Group(children));
+            }
+            b"nSHP" => {
+                let id = content.i32()?;
+                content.dict()?;
+                let mut shape_models = Vec::new();
+                for _ in 0..content.length()? {
+                    shape_models.push(content.i32()?);
+                    content.dict()?;
+                }
+                nodes.insert(id, Node::Shape(shape_models));
+            }
+            // Materials, layers, cameras...
+            _ => (),
+        }
+    }
+
+    let models = if nodes.is_empty() {
+        // Without a scene graph, every model is at the origin
+        models
+            .into_iter()
+            .map(|(size, voxels)| VoxModel {
+                offset: [0; 3],
+                size,
+                voxels,
+            })
+            .collect()
+    } else {
+        let mut walk = SceneWalk {
+            nodes: &nodes,
+            models: &models,
+            path: HashSet::new(),
+            visited: 0,
+            voxels: 0,
+            min: [i64::max_value(); 3],
+            max: [i64::min_value(); 3],
+            placed: Vec::new(),
+        };
+        walk.place_node(0, [0; 3])?;
+        walk.placed
+    };
+    Ok(VoxScene {
+        models,
+        palette: palette.unwrap_or_else(default_palette),
+    })
+}
+
+/// State of the walk placing the models of a scene graph
+struct SceneWalk<'a> {
+    nodes: &'a HashMap<i32, Node>,
+    models: &'a [([u32; 3], Vec<[u8; 4]>)],
+    /// Nodes between the root and the current node, to detect cycles
+    path: HashSet<i32>,
+    /// Number of nodes visited so far
+    visited: usize,
+    /// Number of voxels placed so far
+    voxels: u64,
+    /// Bounding box of the models placed so far
+    min: [i64; 3],
+    max: [i64; 3],
+    placed: Vec<VoxModel>,
+}
+
+impl<'a> SceneWalk<'a> {
+    /// Place the models below a node of the scene graph
+    fn place_node(&mut self, id: i32, translation: [i32; 3]) -> io::Result<()> {
+        if self.path.len() >= MAX_NODE_DEPTH {
+            return Err(invalid("scene graph too deep"));
+        }
+        self.visited += 1;
+        if self.visited > MAX_VISITED_NODES {
+            return Err(invalid("scene graph too large"));
+        }
+        if !self.path.insert(id) {
+            return Err(invalid("cycle in the scene graph"));
+        }
+        match self.nodes.get(&id) {
+            Some(Node::Transform {
+                translation: t,
+                child,
+            }) => {
+                let mut translation = translation;
+                for i in 0..3 {
+                    translation[i] = translation[i]
+                        .checked_add(t[i])
+                        .ok_or_else(|| invalid("translation out of range"))?;
+                }
+                self.place_node(*child, translation)?;
+            }
+            Some(Node::Group(children)) => {
+                for &child in children {
+                    self.place_node(child, translation)?;
+                }
+            }
+            Some(Node::Shape(shape_models)) => {
+                for &model in shape_models {
+                    self.place_model(model, translation)?;
+                }
+            }
+            None => return Err(invalid("unknown node")),
+        }
+        self.path.remove(&id);
+        Ok(())
+    }
+
+    /// Place a model with its center at `translation`
+    fn place_model(&mut self, model: i32, translation: [i32; 3]) -> io::Result<()> {
+        let (size, voxels) = self
+            .models
+            .get(model as usize)
+            .ok_or_else(|| invalid("unknown model"))?;
+        self.voxels += voxels.len() as u64;
+        if self.voxels > MAX_SCENE_VOLUME {
+            return Err(invalid("scene too large"));
+        }
+        // Translations move the center of the models
+        let mut offset = translation;
+        for i in 0..3 {
+            offset[i] = offset[i]
+                .checked_sub((size[i] / 2) as i32)
+                .ok_or_else(|| invalid("translation out of range"))?;
+            self.min[i] = i64::min(self.min[i], i64::from(offset[i]));
+            self.max[i] = i64::max(self.max[i], i64::from(offset[i]) + i64::from(size[i]));
+        }
+        let volume = (0..3).try_fold(1u64, |volume, i| {
+            volume.checked_mul((self.max[i] - self.min[i]) as u64)
+        });
+        match volume {
+            Some(volume) if volume <= MAX_SCENE_VOLUME => (),
+            _ => return Err(invalid("scene too large")),
+        }
+        self.placed.push(VoxModel {
+            offset,
+            size: *size,
+            voxels: voxels.clone(),
+        });
+        Ok(())
+    }
+}
+
+fn write_i32(out: &mut Vec<u8>, value: i32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    write_i32(out, value.len() as i32);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8], content: &[u8]) {
+    out.extend_from_slice(id);
+    write_i32(out, content.len() as i32);
+    write_i32(out, 0);
+    out.extend_from_slice(content);
+}
+
+/// The contents of a `.vox` file holding a scene. The scene graph is a group holding a
+/// transform and a shape for every model.
+pub fn write_vox(scene: &VoxScene) -> Vec<u8> {
+    let mut chunks = Vec::new();
+    for model in &scene.models {
+        let mut size = Vec::new();
+        for &c in &model.size {
+            write_i32(&mut size, c as i32);
+        }
+        write_chunk(&mut chunks, b"SIZE", &size);
+        let mut voxels = Vec::new();
+        write_i32(&mut voxels, model.voxels.len() as i32);
+        for voxel in &model.voxels {
+            voxels.extend_from_slice(voxel);
+        }
+        write_chunk(&mut chunks, b"XYZI", &voxels);
+    }
+
+    // Root transform, with the group as node 1
+    let mut root = Vec::new();
+    for &value in &[0, 0, 1, -1, -1, 1, 0] {
+        write_i32(&mut root, value);
+    }
+    write_chunk(&mut chunks, b"nTRN", &root);
+    let mut group = Vec::new();
+    write_i32(&mut group, 1);
+    write_i32(&mut group, 0);
+    write_i32(&mut group, scene.models.len() as i32);
+    for i in 0..scene.models.len() {
+        write_i32(&mut group, 2 + 2 * i as i32);
+    }
+    write_chunk(&mut chunks, b"nGRP", &group);
+    for (i, model) in scene.models.iter().enumerate() {
+        let transform_id = 2 + 2 * i as i32;
+        let translation: Vec<String> = (0..3)
+            .map(|j| (model.offset[j] + (model.size[j] / 2) as i32).to_string())
+            .collect();
+        let mut transform = Vec::new();
+        for &value in &[transform_id, 0, transform_id + 1, -1, -1, 1, 1] {
+            write_i32(&mut transform, value);
+        }
+        write_string(&mut transform, "_t");
+        write_string(&mut transform, &translation.join(" "));
+        write_chunk(&mut chunks, b"nTRN", &transform);
+        let mut shape = Vec::new();
+        for &value in &[transform_id + 1, 0, 1, i as i32, 0] {
+            write_i32(&mut shape, value);
+        }
+        write_chunk(&mut chunks, b"nSHP", &shape);
+    }
+
+    let mut palette = Vec::new();
+    for color in scene.palette[1..].iter().chain(&[[0, 0, 0, 0]]) {
+        palette.extend_from_slice(color);
+    }
+    write_chunk(&mut chunks, b"RGBA", &palette);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"VOX ");
+    write_i32(&mut out, VOX_VERSION);
+    out.extend_from_slice(b"MAIN");
+    write_i32(&mut out, 0);
+    write_i32(&mut out, chunks.len() as i32);
+    out.extend_from_slice(&chunks);
+    out
+}
+
+/// Lowest corner and highest corner, excluded, of the bounding box of the models. Both are the
+/// origin if there are no models.
+fn scene_bounds(models: &[VoxModel]) -> ([i64; 3], [i64; 3]) {
+    if models.is_empty() {
+        return ([0; 3], [0; 3]);
+    }
+    let mut min = [i64::max_value(); 3];
+    let mut max = [i64::min_value(); 3];
+    for model in models {
+        for i in 0..3 {
+            let offset = i64::from(model.offset[i]);
+            min[i] = i64::min(min[i], offset);
+            max[i] = i64::max(max[i], offset + i64::from(model.size[i]));
+        }
+    }
+    (min, max)
+}
+
+/// The blocks of a scene. The region is the bounding box of the models, and empty voxels are
+/// air.
+pub fn scene_to_region(scene: &VoxScene, colors: &ColorTable) -> BlockRegion {
+    let air = BlockId::from(0);
+    if scene.models.is_empty() {
+        return BlockRegion::new([0; 3], air);
+    }
+    let (min, max) = scene_bounds(&scene.models);
+    let extent = [
+        (max[0] - min[0]) as usize,
+        (max[1] - min[1]) as usize,
+        (max[2] - min[2]) as usize,
+    ];
+
+    let blocks: Vec<BlockId> = scene
+        .palette
+        .iter()
+        .map(|&[r, g, b, _]| colors.block([r, g, b]))
+        .collect();
+    let mut region = BlockRegion::new([extent[0], extent[2], extent[1]], air);
+    for model in &scene.models {
+        for voxel in &model.voxels {
+            let mut pos = [0; 3];
+            for i in 0..3 {
+                pos[i] = (i64::from(model.offset[i]) + i64::from(voxel[i]) - min[i]) as usize;
+            }
+            let block = if voxel[3] == 0 {
+                air
+            } else {
+                blocks[voxel[3] as usize]
+            };
+            region.set([pos[0], pos[2], extent[1] - 1 - pos[1]], block);
+        }
+    }
+    region
+}
+
+/// A scene holding the blocks of a region, split into models of at most 256³ voxels. Fails if
+/// some blocks have no colour, or if there are more than 255 kinds of blocks.
+pub fn region_to_scene(region: &BlockRegion, colors: &ColorTable) -> Result<VoxScene, String> {
+    let air = BlockId::from(0);
+    let [sx, sy, sz] = region.size();
+
+    // Give a colour index to every kind of block
+    let mut palette = default_palette();
+    let mut indices = HashMap::new();
+    for x in 0..sx {
+        for y in 0..sy {
+            for z in 0..sz {
+                let block = region.get([x, y, z]);
+                if block == air || indices.contains_key(&block) {
+                    continue;
+                }
+                let [r, g, b] = colors
+                    .color(block)
+                    .ok_or_else(|| format!("block {} has no colour", colors.block_name(block)))?;
+                let index = indices.len() + 1;
+                if index >= palette.len() {
+                    return Err("more than 255 kinds of blocks".to_owned());
+                }
+                palette[index] = [r, g, b, 0xff];
+                indices.insert(block, index as u8);
+            }
+        }
+    }
+
+    // The region's z axis becomes MagicaVoxel's y axis, pointing the other way
+    let mut models = Vec::new();
+    for x0 in (0..sx).step_by(MAX_MODEL_SIZE) {
+        for y0 in (0..sy).step_by(MAX_MODEL_SIZE) {
+            for z0 in (0..sz).step_by(MAX_MODEL_SIZE) {
+                let x1 = usize::min(sx, x0 + MAX_MODEL_SIZE);
+                let y1 = usize::min(sy, y0 + MAX_MODEL_SIZE);
+                let z1 = usize::min(sz, z0 + MAX_MODEL_SIZE);
+                let mut voxels = Vec::new();
+                for x in x0..x1 {
+                    for y in y0..y1 {
+                        for z in z0..z1 {
+                            if let Some(&index) = indices.get(&region.get([x, y, z])) {
+                                voxels.push([
+                                    (x - x0) as u8,
+                                    (z1 - 1 - z) as u8,
+                                    (y - y0) as u8,
+                                    index,
+                                ]);
+                            }
+                        }
+                    }
+                }
+                models.push(VoxModel {
+                    offset: [x0 as i32, (sz - z1) as i32, y0 as i32],
+                    size: [(x1 - x0) as u32, (z1 - z0) as u32, (y1 - y0) as u32],
+                    voxels,
+                });
+            }
+        }
+    }
+    Ok(VoxScene { models, palette })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{create_headless_block_registry, load_block_definitions};
+
+    fn setup() -> (BlockRegistry, ColorTable) {
+        let definitions = load_block_definitions(Path::new("assets/blocks.toml"));
+        let registry = create_headless_block_registry(&definitions);
+        let colors = load_color_table(Path::new("assets/vox_colors.toml"), &registry);
+        (registry, colors)
+    }
+
+    fn round_trip(region: &BlockRegion, colors: &ColorTable) -> BlockRegion {
+        let scene = region_to_scene(region, colors).unwrap();
+        let bytes = write_vox(&scene);
+        let read = read_vox(&bytes).unwrap();
+        assert_eq!(read, scene);
+        scene_to_region(&read, colors)
+    }
+
+    #[test]
+    fn default_palette_size() {
+        let palette = default_palette();
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[1], [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(palette[255], [0x11, 0x11, 0x11, 0xff]);
+    }
+
+    #[test]
+    fn read_single_model() {
+        let (registry, colors) = setup();
+        let scene = load_vox(Path::new("assets/vox/hut.vox")).unwrap();
+        assert_eq!(scene.models.len(), 1);
+        let region = scene_to_region(&scene, &colors);
+        // MagicaVoxel's (x, y, z) size becomes (x, z, y)
+        let size = scene.models[0].size;
+        assert_eq!(
+            region.size(),
+            [size[0] as usize, size[2] as usize, size[1] as usize]
+        );
+        // Stone floor, wooden walls and a roof of leaves
+        let block = |name| registry.get_id(name).unwrap();
+        assert_eq!(region.get([0, 0, 0]), block("stone"));
+        assert_eq!(region.get([0, 1, 0]), block("wood"));
+        assert_eq!(region.get([2, 2, 2]), BlockId::from(0));
+        assert_eq!(region.get([2, region.size()[1] - 1, 2]), block("leaves"));
+        assert_eq!(round_trip(&region, &colors), region);
+    }
+
+    #[test]
+    fn read_scene_graph() {
+        let (_, colors) = setup();
+        let scene = load_vox(Path::new("assets/vox/towers.vox")).unwrap();
+        // The second model is used twice
+        assert_eq!(scene.models.len(), 3);
+        assert_eq!(scene.models[1].voxels, scene.models[2].voxels);
+        assert_ne!(scene.models[1].offset, scene.models[2].offset);
+        let region = scene_to_region(&scene, &colors);
+        let voxels: usize = scene.models.iter().map(|m| m.voxels.len()).sum();
+        let [sx, sy, sz] = region.size();
+        let mut blocks = 0;
+        for x in 0..sx {
+            for y in 0..sy {
+                for z in 0..sz {
+                    if region.get([x, y, z]) != BlockId::from(0) {
+                        blocks += 1;
+                    }
+                }
+            }
+        }
+        // The models don't overlap
+        assert_eq!(blocks, voxels);
+        assert_eq!(round_trip(&region, &colors), region);
+    }
+
+    /// Chunks of a model of a single voxel
+    fn single_voxel() -> Vec<u8> {
+        let mut chunks = Vec::new();
+        let mut size = Vec::new();
+        for _ in 0..3 {
+            write_i32(&mut size, 1);
+        }
+        write_chunk(&mut chunks, b"SIZE", &size);
+        let mut voxels = Vec::new();
+        write_i32(&mut voxels, 1);
+        voxels.extend_from_slice(&[0, 0, 0, 1]);
+        write_chunk(&mut chunks, b"XYZI", &voxels);
+        chunks
+    }
+
+    /// A file made of the children of its MAIN chunk
+    fn vox_file(chunks: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"VOX ");
+        write_i32(&mut out, VOX_VERSION);
+        out.extend_from_slice(b"MAIN");
+        write_i32(&mut out, 0);
+        write_i32(&mut out, chunks.len() as i32);
+        out.extend_from_slice(chunks);
+        out
+    }
+
+    /// A file with a model of a single voxel, below a transform for every frame attributes
+    fn transformed_voxel(frames: &[&[(&str, &str)]]) -> Vec<u8> {
+        let mut chunks = single_voxel();
+        for (id, attributes) in frames.iter().enumerate() {
+            let mut transform = Vec::new();
+            for &value in &[id as i32, 0, id as i32 + 1, -1, -1, 1] {
+                write_i32(&mut transform, value);
+            }
+            write_i32(&mut transform, attributes.len() as i32);
+            for (key, value) in attributes.iter() {
+                write_string(&mut transform, key);
+                write_string(&mut transform, value);
+            }
+            write_chunk(&mut chunks, b"nTRN", &transform);
+        }
+        let mut shape = Vec::new();
+        for &value in &[frames.len() as i32, 0, 1, 0, 0] {
+            write_i32(&mut shape, value);
+        }
+        write_chunk(&mut chunks, b"nSHP", &shape);
+        vox_file(&chunks)
+    }
+
+    /// A file with a group for every list of children, the group `i` having the id `i`, and a
+    /// shape of a single voxel with the id `groups.len()`
+    fn grouped_voxel(groups: &[&[i32]]) -> Vec<u8> {
+        let mut chunks = single_voxel();
+        for (id, children) in groups.iter().enumerate() {
+            let mut group = Vec::new();
+            for &value in &[id as i32, 0, children.len() as i32] {
+                write_i32(&mut group, value);
+            }
+            for &child in children.iter() {
+                write_i32(&mut group, child);
+            }
+            write_chunk(&mut chunks, b"nGRP", &group);
+        }
+        let mut shape = Vec::new();
+        for &value in &[groups.len() as i32, 0, 1, 0, 0] {
+            write_i32(&mut shape, value);
+        }
+        write_chunk(&mut chunks, b"nSHP", &shape);
+        vox_file(&chunks)
+    }
+
+    #[test]
+    fn shared_groups() {
+        // A group placing the shape twice is fine
+        let scene = read_vox(&grouped_voxel(&[&[1, 1]])).unwrap();
+        assert_eq!(scene.models.len(), 2);
+        // A group listing itself is a cycle
+        let error = read_vox(&grouped_voxel(&[&[0, 0]])).unwrap_err();
+        assert_eq!(error.to_string(), "cycle in the scene graph");
+        let error = read_vox(&grouped_voxel(&[&[1], &[2, 0], &[3]])).unwrap_err();
+        assert_eq!(error.to_string(), "cycle in the scene graph");
+        // Every group lists the next one twice, which would place 2^40 models
+        let groups: Vec<[i32; 2]> = (1..=40).map(|i| [i, i]).collect();
+        let groups: Vec<&[i32]> = groups.iter().map(|g| &g[..]).collect();
+        assert!(read_vox(&grouped_voxel(&groups)).is_err());
+    }
+
+    #[test]
+    fn rotations() {
+        let scene = read_vox(&transformed_voxel(&[&[("_r", "4"), ("_t", "1 2 3")]])).unwrap();
+        assert_eq!(scene.models[0].offset, [1, 2, 3]);
+        assert!(read_vox(&transformed_voxel(&[&[("_r", "20")]])).is_err());
+    }
+
+    #[test]
+    fn translation_overflow() {
+        let frames: &[&[_]] = &[&[("_t", "2147483647 0 0")], &[("_t", "1 0 0")]];
+        assert!(read_vox(&transformed_voxel(frames)).is_err());
+    }
+
+    #[test]
+    fn scene_too_large() {
+        let model = |offset| VoxModel {
+            offset,
+            size: [1, 1, 1],
+            voxels: vec![[0, 0, 0, 1]],
+        };
+        let mut scene = VoxScene {
+            models: vec![model([0, 0, 0]), model([1000, 1000, 10])],
+            palette: default_palette(),
+        };
+        assert!(read_vox(&write_vox(&scene)).is_ok());
+        scene.models[1].offset = [100_000, 100_000, 0];
+        assert!(read_vox(&write_vox(&scene)).is_err());
+    }
+
+    #[test]
+    fn large_region_round_trip() {
+        let (registry, colors) = setup();
+        let stone = registry.get_id("stone").unwrap();
+        let sand = registry.get_id("sand").unwrap();
+        let mut region = BlockRegion::new([300, 5, 260], BlockId::from(0));
+        for x in 0..300 {
+            for z in 0..260 {
+                let block = if (x + z) % 3 == 0 { stone } else { sand };
+                region.set([x, (x * z) % 5, z], block);
+            }
+        }
+        assert_eq!(region_to_scene(&region, &colors).unwrap().models.len(), 4);
+        assert_eq!(round_trip(&region, &colors), region);
+    }
+}
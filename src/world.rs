@@ -0,0 +1,233 @@
+//! Worlds, stored in a directory holding a `world.toml` metadata file, the region files and a
+//! `players` directory with a record of every player.
+
+use crate::block::{BlockId, BlockIdMap, BlockRegistry, BlockTable};
+use crate::config::Config;
+use crate::player::PlayerRecord;
+use crate::region::RegionStorage;
+use crate::util::write_atomic;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Version of the layout of the world directory. Worlds with a newer version can't be opened.
+pub const WORLD_FORMAT_VERSION: u32 = 1;
+
+/// Contents of `remap.toml`, which exists while the saved chunks are converted to other block
+/// ids. An interrupted conversion is finished the next time the world is opened, and the chunks
+/// that were already converted are not converted again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RemapProgress {
+    /// Whether every region was converted, in which case the converted regions only have to
+    /// replace the old ones
+    converted: bool,
+    /// Block ids before and after the conversion
+    from: BTreeMap<String, BlockId>,
+    to: BTreeMap<String, BlockId>,
+}
+
+/// Contents of `world.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldInfo {
+    pub format_version: u32,
+    pub seed: u64,
+    /// Name of the world generator, see `server::worldgen::GENERATORS`
+    pub generator: String,
+    /// Where new players appear
+    pub spawn: [f64; 3],
+    /// Time spent in the world, in seconds
+    pub time: f64,
+    /// Id of every block in the saved chunks. Sorted by name so that the file is easy to read.
+    pub blocks: BTreeMap<String, BlockId>,
+}
+
+/// An open world
+pub struct World {
+    path: PathBuf,
+    pub info: WorldInfo,
+    pub storage: RegionStorage,
+}
+
+impl World {
+    /// Open the world at `path`, or create it with the seed and generator of the config if it
+    /// doesn't exist. `find_spawn` picks the spawn point of new worlds.
+    /// The saved chunks are remapped if the world was saved with other block ids, or if their
+    /// remapping was interrupted.
+    pub fn open(
+        path: &Path,
+        config: &Config,
+        block_registry: &BlockRegistry,
+        find_spawn: impl FnOnce(&WorldInfo) -> [f64; 3],
+    ) -> io::Result<Self> {
+        let storage = RegionStorage::new(&path.join("region"))?;
+        fs::create_dir_all(path.join("players"))?;
+        let info_path = path.join("world.toml");
+        let (mut info, table) = match fs::read_to_string(&info_path) {
+            Ok(buf) => {
+                let info: WorldInfo = toml::from_str(&buf)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                if info.format_version > WORLD_FORMAT_VERSION {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "the world has format version {}, newer than {}",
+                            info.format_version, WORLD_FORMAT_VERSION
+                        ),
+                    ));
+                }
+                let table = info.blocks.clone().into_iter().collect();
+                (info, Some(table))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                let mut info = WorldInfo {
+                    format_version: WORLD_FORMAT_VERSION,
+                    // TOML integers are signed
+                    seed: config
+                        .world_seed
+                        .unwrap_or_else(|| rand::random::<u64>() >> 1),
+                    generator: config.world_generator.clone(),
+                    spawn: [0.0; 3],
+                    time: 0.0,
+                    blocks: BTreeMap::new(),
+                };
+                info.spawn = find_spawn(&info);
+                println!("Created world {} with seed {}", path.display(), info.seed);
+                (info, read_legacy_block_table(path)?)
+            }
+            Err(e) => return Err(e),
+        };
+
+        // The chunks use the ids of an interrupted remapping, or the ids it was converting to
+        // once it is finished
+        let table = match read_remap_progress(path)? {
+            Some(progress) => {
+                println!("Finishing the interrupted remapping of the block ids of the world");
+                let to = progress.to.clone().into_iter().collect();
+                remap_chunks(path, &storage, progress)?;
+                Some(to)
+            }
+            None => table,
+        };
+        if let Some(table) = table {
+            let id_map = BlockIdMap::new(&table, block_registry);
+            if !id_map.is_identity() {
+                for name in &id_map.missing {
+                    println!(
+                        "WARNING: block {} no longer exists, replacing it by air",
+                        name
+                    );
+                }
+                println!("Remapping the block ids of the world");
+                let progress = RemapProgress {
+                    converted: false,
+                    from: table.into_iter().collect(),
+                    to: block_registry.block_table().into_iter().collect(),
+                };
+                remap_chunks(path, &storage, progress)?;
+            }
+        }
+        info.blocks = block_registry.block_table().into_iter().collect();
+
+        let world = Self {
+            path: path.to_owned(),
+            info,
+            storage,
+        };
+        world.save_info()?;
+        // The ids of the chunks are in `world.toml` now
+        remove_file_if_exists(&path.join("remap.toml"))?;
+        remove_file_if_exists(&path.join("blocks.toml"))?;
+        Ok(world)
+    }
+
+    /// Write `world.toml`
+    pub fn save_info(&self) -> io::Result<()> {
+        write_atomic(
+            &self.path.join("world.toml"),
+            toml::to_string(&self.info).unwrap().as_bytes(),
+        )
+    }
+
+    /// Read the record of a player, `None` if they never played in this world. The name must be
+    /// valid, see `player::is_valid_player_name`.
+    pub fn load_player(&self, name: &str) -> io::Result<Option<PlayerRecord>> {
+        match fs::read_to_string(self.player_path(name)) {
+            Ok(buf) => toml::from_str(&buf)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save_player(&self, name: &str, record: &PlayerRecord) -> io::Result<()> {
+        write_atomic(
+            &self.player_path(name),
+            toml::to_string(record).unwrap().as_bytes(),
+        )
+    }
+
+    fn player_path(&self, name: &str) -> PathBuf {
+        self.path.join("players").join(format!("{}.toml", name))
+    }
+}
+
+/// Worlds created before `world.toml` existed store their block table in `blocks.toml`
+fn read_legacy_block_table(path: &Path) -> io::Result<Option<BlockTable>> {
+    let table_path = path.join("blocks.toml");
+    match fs::read_to_string(&table_path) {
+        Ok(buf) => {
+            let table =
+                toml::from_str(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok(Some(table))
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Read `remap.toml`, `None` if no remapping was interrupted
+fn read_remap_progress(path: &Path) -> io::Result<Option<RemapProgress>> {
+    match fs::read_to_string(path.join("remap.toml")) {
+        Ok(buf) => toml::from_str(&buf)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Convert the saved chunks, or finish converting them, recording the progress in `remap.toml`.
+/// The file must be removed once the new ids are saved in `world.toml`.
+fn remap_chunks(
+    path: &Path,
+    storage: &RegionStorage,
+    mut progress: RemapProgress,
+) -> io::Result<()> {
+    let progress_path = path.join("remap.toml");
+    if !progress.converted {
+        write_atomic(
+            &progress_path,
+            toml::to_string(&progress).unwrap().as_bytes(),
+        )?;
+        let from = progress.from.clone().into_iter().collect();
+        let to = progress.to.clone().into_iter().collect();
+        storage.remap_blocks(&BlockIdMap::from_tables(&from, &to))?;
+        progress.converted = true;
+        write_atomic(
+            &progress_path,
+            toml::to_string(&progress).unwrap().as_bytes(),
+        )?;
+    }
+    storage.finish_remap()
+}
+
+/// Remove a file that may not exist
+fn remove_file_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}